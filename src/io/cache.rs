@@ -0,0 +1,649 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use super::net::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use super::net;
+use crate::utils::config::Config;
+use crate::utils::utils;
+
+/// How long a cached packument is used without asking the registry, in
+/// seconds, unless the `cache-max-age` setting says otherwise.
+pub const DEFAULT_METADATA_MAX_AGE: u64 = 300;
+
+/// How far the cache is trusted in place of the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheMode {
+    /// Cached packuments are revalidated once older than `cache-max-age`.
+    #[default]
+    Online,
+    /// Anything in the cache is used without revalidation; only what's
+    /// missing is fetched.
+    PreferOffline,
+    /// Nothing is fetched, and anything missing from the cache is an error.
+    Offline,
+}
+
+impl CacheMode {
+    /// Reads the `offline` and `prefer-offline` settings.
+    pub fn from_config(config: &Config) -> Self {
+        if config.get_bool("offline") {
+            CacheMode::Offline
+        } else if config.get_bool("prefer-offline") {
+            CacheMode::PreferOffline
+        } else {
+            CacheMode::Online
+        }
+    }
+
+    /// Whether cached packages and metadata are used without asking the
+    /// registry.
+    pub fn trusts_cache(self) -> bool {
+        self != CacheMode::Online
+    }
+
+    /// The error for something that would need the network in offline mode.
+    pub fn offline_error(what: &str) -> String {
+        format!("{} is not in the cache, and snpm is running with --offline", what)
+    }
+}
+
+/// A packument stored in the snpm cache with the validators the registry
+/// sent along with it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// When the packument was last fetched or revalidated, in seconds since
+    /// the Unix epoch.
+    pub fetched_at: u64,
+    pub packument: Value,
+}
+
+impl CachedMetadata {
+    pub fn new(packument: Value, etag: Option<String>, last_modified: Option<String>) -> Self {
+        CachedMetadata {
+            etag,
+            last_modified,
+            fetched_at: now(),
+            packument,
+        }
+    }
+
+    /// Seconds since the packument was last fetched or revalidated.
+    pub fn age(&self) -> u64 {
+        now().saturating_sub(self.fetched_at)
+    }
+
+    /// Marks the packument as just revalidated.
+    pub fn touch(&mut self) {
+        self.fetched_at = now();
+    }
+}
+
+/// The cached packument for the metadata document at `url`, if `cache_dir`
+/// has one. Entries that can't be read are treated as missing.
+pub fn load_metadata(cache_dir: &Path, url: &str) -> Option<CachedMetadata> {
+    let content = fs::read(metadata_path(cache_dir, url)).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+pub fn store_metadata(cache_dir: &Path, url: &str, metadata: &CachedMetadata) -> Result<(), Box<dyn std::error::Error>> {
+    let path = metadata_path(cache_dir, url);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&path, &serde_json::to_vec(metadata)?)?;
+    Ok(())
+}
+
+/// A registry package in the cache. Packages are keyed by name and version,
+/// so every project installing that version shares one download.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedPackage {
+    pub name: String,
+    pub version: String,
+    /// Subresource integrity of the tarball: what the registry announced
+    /// plus the sha512 computed on download.
+    pub integrity: String,
+    pub tarball: String,
+}
+
+/// Everything snpm creates in the cache directory. The directory itself
+/// may be shared with other files, so only these are ever removed.
+pub const CACHE_CONTENTS: &[&str] = &[
+    "_packages",
+    "_metadata",
+    "_tarball",
+    "_file",
+    "_git",
+    "_npx",
+    "index.json",
+    "index.json.lock",
+];
+
+/// Directory holding one entry per cached package.
+pub fn packages_dir() -> PathBuf {
+    utils::get_cache_directory().join("_packages")
+}
+
+/// Directory of the cache entry for `name@version` in `cache_dir`, holding
+/// the tarball, its extracted contents and an `entry.json` describing it.
+pub fn package_dir(cache_dir: &Path, name: &str, version: &str) -> PathBuf {
+    cache_dir.join("_packages").join(format!("{}@{}", package_key(name), version))
+}
+
+/// `name` as it appears in the directory names of [`package_dir`].
+pub fn package_key(name: &str) -> String {
+    name.replace('/', "+")
+}
+
+/// Every package entry in the cache, with its `entry.json` if it could be
+/// read.
+pub fn package_entries() -> Vec<(PathBuf, Option<CachedPackage>)> {
+    let Ok(entries) = fs::read_dir(packages_dir()) else {
+        return vec![];
+    };
+
+    let mut packages: Vec<(PathBuf, Option<CachedPackage>)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && !is_temporary(path))
+        .map(|path| {
+            let entry = fs::read(path.join("entry.json"))
+                .ok()
+                .and_then(|content| serde_json::from_slice(&content).ok());
+            (path, entry)
+        })
+        .collect();
+    packages.sort_by(|(a, _), (b, _)| a.cmp(b));
+    packages
+}
+
+/// Re-hashes the tarball of a package entry against the integrity recorded
+/// for it and checks that its contents are complete.
+pub fn verify_package(dir: &Path) -> Result<CachedPackage, String> {
+    let entry: CachedPackage = fs::read(dir.join("entry.json"))
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .ok_or("missing or unreadable entry.json")?;
+    let mut hashes = Hashes::default();
+    File::open(dir.join("package.tgz"))
+        .and_then(|mut tarball| std::io::copy(&mut tarball, &mut hashes))
+        .map_err(|e| format!("unreadable tarball: {}", e))?;
+    hashes.check(&entry.integrity)?;
+
+    let contents = dir.join("package");
+    if utils::read_json(&contents.join("package.json"))
+        .ok()
+        .is_none_or(|manifest| manifest["version"].as_str() != Some(entry.version.as_str()))
+    {
+        return Err("extracted contents are incomplete".to_string());
+    }
+
+    Ok(entry)
+}
+
+/// The extracted contents of `name@version` if the cache holds a complete
+/// entry for it whose integrity agrees with `integrity`, when one is known.
+pub fn cached_package(cache_dir: &Path, name: &str, version: &str, integrity: Option<&str>) -> Option<PathBuf> {
+    cached_entry(&package_dir(cache_dir, name, version), integrity)
+        .filter(|(_, entry)| entry.version == version)
+        .map(|(contents, _)| contents)
+}
+
+/// The extracted contents of the tarball entry at `dir` and the integrity
+/// recorded for it, if the entry is complete and agrees with `integrity`.
+pub fn cached_tarball(dir: &Path, integrity: Option<&str>) -> Option<(PathBuf, String)> {
+    cached_entry(dir, integrity).map(|(contents, entry)| (contents, entry.integrity))
+}
+
+fn cached_entry(dir: &Path, integrity: Option<&str>) -> Option<(PathBuf, CachedPackage)> {
+    let entry: CachedPackage = serde_json::from_slice(&fs::read(dir.join("entry.json")).ok()?).ok()?;
+    if integrity.is_some_and(|expected| !shares_hash(expected, &entry.integrity)) {
+        return None;
+    }
+
+    let contents = dir.join("package");
+    contents.join("package.json").is_file().then_some((contents, entry))
+}
+
+/// Downloads `name@version` into the cache, checking it against
+/// `integrity` when known, and returns its extracted contents. The entry is
+/// built in a temporary directory and renamed into place under an exclusive
+/// lock, so other processes never see it half written.
+pub fn add_package(
+    client: &Client,
+    name: &str,
+    version: &str,
+    tarball: &str,
+    integrity: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = package_dir(client.cache_dir(), name, version);
+    let _lock = lock_entry(&dir, true)?;
+    // Another process may have added it while we waited for the lock.
+    if let Some(contents) = cached_package(client.cache_dir(), name, version, integrity) {
+        return Ok(contents);
+    }
+
+    download_entry(client, &dir, tarball, integrity, Some((name, version)))?;
+    Ok(dir.join("package"))
+}
+
+/// Downloads the tarball at `url` into the cache entry `dir` the way
+/// [`add_package`] does, for dependencies on a tarball URL. Returns the
+/// extracted contents and the tarball's integrity.
+pub fn add_tarball(
+    client: &Client,
+    dir: &Path,
+    url: &str,
+    integrity: Option<&str>,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    let _lock = lock_entry(dir, true)?;
+    if let Some(cached) = cached_tarball(dir, integrity) {
+        return Ok(cached);
+    }
+
+    let entry = download_entry(client, dir, url, integrity, None)?;
+    Ok((dir.join("package"), entry.integrity))
+}
+
+/// Fills the entry `dir` with the tarball at `url`: the tarball itself,
+/// its contents and an `entry.json`. `package` names the entry; without it
+/// the name and version are read from the tarball's package.json.
+fn download_entry(
+    client: &Client,
+    dir: &Path,
+    url: &str,
+    integrity: Option<&str>,
+    package: Option<(&str, &str)>,
+) -> Result<CachedPackage, Box<dyn std::error::Error>> {
+    let mut written = None;
+    fill_entry(dir, |temp| {
+        net::download(client, url, |response| {
+            // Clear what an interrupted earlier attempt left behind.
+            if temp.exists() {
+                fs::remove_dir_all(temp)?;
+            }
+            fs::create_dir_all(temp)?;
+            // The body goes straight through gzip and tar into the entry,
+            // hashed and copied to package.tgz on the way.
+            let mut download = Tee {
+                reader: response,
+                hashes: Hashes::default(),
+                copy: BufWriter::new(File::create(temp.join("package.tgz"))?),
+            };
+            net::unpack_tarball(&mut download, url, &temp.join("package"))?;
+            download.copy.flush()?;
+            if let Some(expected) = integrity {
+                download.hashes.check(expected)?;
+            }
+
+            let (name, version) = match package {
+                Some((name, version)) => (name.to_string(), version.to_string()),
+                None => {
+                    let manifest = utils::read_json(&temp.join("package").join("package.json"))?;
+                    let field = |key: &str| manifest[key].as_str().unwrap_or_default().to_string();
+                    (field("name"), field("version"))
+                }
+            };
+            let computed = format!("sha512-{}", download.hashes.digest("sha512").unwrap_or_default());
+            let entry = CachedPackage {
+                name,
+                version,
+                integrity: match integrity {
+                    Some(expected) if expected.split_whitespace().any(|hash| hash == computed) => expected.to_string(),
+                    Some(expected) => format!("{} {}", expected, computed),
+                    None => computed,
+                },
+                tarball: url.to_string(),
+            };
+            fs::write(temp.join("entry.json"), serde_json::to_vec_pretty(&entry)?)?;
+            written = Some(entry);
+            Ok(())
+        })
+    })?;
+
+    written.ok_or_else(|| format!("Failed to cache {}", url).into())
+}
+
+/// Builds the cache entry `dir` by letting `populate` fill a temporary
+/// directory, which is then renamed over `dir`, so that nobody sees the
+/// entry half written. The caller holds the entry's exclusive lock.
+pub fn fill_entry(
+    dir: &Path,
+    populate: impl FnOnce(&Path) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp = temp_path(dir);
+    if let Err(e) = populate(&temp).and_then(|()| replace_dir(&temp, dir).map_err(Into::into)) {
+        let _ = fs::remove_dir_all(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Locks the cache entry at `dir` until the returned file is dropped.
+/// Writing or removing an entry takes the lock exclusively; copying out of
+/// it shares the lock. The lock belongs to the open `<dir>.lock` file, so
+/// two calls block each other even within one process, and the OS releases
+/// it when snpm is killed.
+pub fn lock_entry(dir: &Path, exclusive: bool) -> std::io::Result<File> {
+    let path = lock_path(dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    loop {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        // The lock file may have been removed along with its entry while
+        // we waited; a lock on the removed file protects nothing.
+        if is_same_file(&file, &path) {
+            return Ok(file);
+        }
+    }
+}
+
+/// Locks the entry at `dir` exclusively like `lock_entry`, but gives up
+/// with `None` instead of waiting while someone else holds the lock.
+pub fn try_lock_entry(dir: &Path) -> std::io::Result<Option<File>> {
+    let path = lock_path(dir);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    loop {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => return Ok(None),
+            Err(std::fs::TryLockError::Error(e)) => return Err(e),
+        }
+        if is_same_file(&file, &path) {
+            return Ok(Some(file));
+        }
+    }
+}
+
+/// Removes the lock file of the entry at `dir`, which must be locked
+/// exclusively by `lock` and already removed itself. Anyone still waiting
+/// for the old lock file notices and locks a new one.
+pub fn remove_lock(dir: &Path, lock: File) {
+    let _ = fs::remove_file(lock_path(dir));
+    drop(lock);
+}
+
+fn lock_path(dir: &Path) -> PathBuf {
+    let mut path = dir.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Windows doesn't remove files that are still open, so the lock file can
+/// only be gone if it was removed before we opened it.
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it over
+/// `path`, so readers see either the old or the new contents.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp = temp_path(path);
+    if let Err(e) = fs::write(&temp, data).and_then(|()| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Moves the directory `from` to `to`, replacing whatever was there.
+fn replace_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    if to.exists() {
+        let old = temp_path(to);
+        fs::rename(to, &old)?;
+        let _ = fs::remove_dir_all(&old);
+    }
+    fs::rename(from, to)
+}
+
+/// Whether `path` is an entry still being written, or left behind by a
+/// process that was killed while writing it.
+pub fn is_temporary(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().contains(".tmp-"))
+}
+
+/// A sibling of `path` no other process or thread will pick.
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".tmp-{}-{:?}-{}", std::process::id(), std::thread::current().id(), nanos));
+    PathBuf::from(temp)
+}
+
+/// The integrity of a registry `dist` object, from `integrity` or, for old
+/// packages, the hex sha1 in `shasum`.
+pub fn dist_integrity(dist: &Value) -> Option<String> {
+    if let Some(integrity) = dist["integrity"].as_str() {
+        return Some(integrity.to_string());
+    }
+
+    let shasum = dist["shasum"].as_str()?;
+    let bytes = (0..shasum.len())
+        .step_by(2)
+        .map(|i| shasum.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    Some(format!("sha1-{}", STANDARD.encode(bytes)))
+}
+
+/// Running digests in the algorithms packages are published with.
+#[derive(Default)]
+struct Hashes {
+    sha512: Sha512,
+    sha256: Sha256,
+    sha1: Sha1,
+}
+
+impl Hashes {
+    fn update(&mut self, data: &[u8]) {
+        self.sha512.update(data);
+        self.sha256.update(data);
+        self.sha1.update(data);
+    }
+
+    /// The base64 digest of everything hashed so far in `algorithm`.
+    fn digest(&self, algorithm: &str) -> Option<String> {
+        match algorithm {
+            "sha512" => Some(STANDARD.encode(self.sha512.clone().finalize())),
+            "sha256" => Some(STANDARD.encode(self.sha256.clone().finalize())),
+            "sha1" => Some(STANDARD.encode(self.sha1.clone().finalize())),
+            _ => None,
+        }
+    }
+
+    /// Checks what was hashed against a subresource integrity string, which
+    /// passes when any of its hashes in a supported algorithm matches.
+    fn check(&self, integrity: &str) -> Result<(), String> {
+        let mut checked = false;
+        for hash in integrity.split_whitespace() {
+            let Some((algorithm, expected)) = hash.split_once('-') else {
+                continue;
+            };
+            let Some(actual) = self.digest(algorithm) else {
+                continue;
+            };
+            if actual == expected.split('?').next().unwrap_or(expected) {
+                return Ok(());
+            }
+            checked = true;
+        }
+
+        if checked {
+            Err(format!("integrity check failed (expected {})", integrity))
+        } else {
+            Err(format!("unsupported integrity '{}'", integrity))
+        }
+    }
+}
+
+impl Write for Hashes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Passes a download through while hashing it and copying it to `copy`.
+struct Tee<R, W> {
+    reader: R,
+    hashes: Hashes,
+    copy: W,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hashes.update(&buf[..read]);
+        self.copy.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+/// Whether two integrity strings have a hash in common.
+fn shares_hash(a: &str, b: &str) -> bool {
+    a.split_whitespace().any(|hash| b.split_whitespace().any(|other| other == hash))
+}
+
+/// Cached packument files, together with the package name each holds, or
+/// `None` for files that can't be read.
+pub fn metadata_entries() -> Vec<(PathBuf, Option<String>)> {
+    let Ok(entries) = fs::read_dir(utils::get_cache_directory().join("_metadata")) else {
+        return vec![];
+    };
+
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let name = fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice::<CachedMetadata>(&content).ok())
+                .map(|metadata| metadata.packument["name"].as_str().unwrap_or_default().to_string());
+            (path, name)
+        })
+        .collect()
+}
+
+/// Packuments are keyed by their full URL, so that switching registries
+/// never serves one registry's metadata for another.
+fn metadata_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    let key: String = without_scheme
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if "/\\:*?\"<>| ".contains(c) { '_' } else { c })
+        .collect();
+    cache_dir.join("_metadata").join(format!("{}.json", key))
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// sha1 of `hello`.
+    const HELLO_SHASUM: &str = "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d";
+
+    fn hashes_of(data: &[u8]) -> Hashes {
+        let mut hashes = Hashes::default();
+        hashes.update(data);
+        hashes
+    }
+
+    #[test]
+    fn prefers_the_integrity_field() {
+        let dist = json!({ "integrity": "sha512-abc", "shasum": HELLO_SHASUM });
+        assert_eq!(dist_integrity(&dist).as_deref(), Some("sha512-abc"));
+    }
+
+    #[test]
+    fn converts_a_hex_shasum() {
+        let integrity = dist_integrity(&json!({ "shasum": HELLO_SHASUM })).unwrap();
+        assert_eq!(integrity, "sha1-qvTGHdzF6KLavt4PO0gs2a6pQ00=");
+        assert!(hashes_of(b"hello").check(&integrity).is_ok());
+        assert!(hashes_of(b"hello!").check(&integrity).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_or_malformed_shasums() {
+        assert_eq!(dist_integrity(&json!({})), None);
+        assert_eq!(dist_integrity(&json!({ "shasum": "xyz" })), None);
+        assert_eq!(dist_integrity(&json!({ "shasum": "abc" })), None);
+    }
+
+    #[test]
+    fn checks_any_supported_hash() {
+        let hashes = hashes_of(b"hello");
+        let sha512 = format!("sha512-{}", hashes.digest("sha512").unwrap());
+        assert!(hashes.check(&sha512).is_ok());
+        assert!(hashes.check(&format!("sha256-wrong {}", sha512)).is_ok());
+        assert!(hashes
+            .check("sha512-wrong")
+            .unwrap_err()
+            .starts_with("integrity check failed"));
+        assert!(hashes.check("md5-XUFAKrxLKna5cZ2REBfFkg==").unwrap_err().starts_with("unsupported"));
+        assert!(shares_hash(&format!("sha1-x {}", sha512), &sha512));
+        assert!(!shares_hash("sha1-x", &sha512));
+    }
+
+    #[test]
+    fn try_lock_gives_up_on_a_locked_entry() {
+        let dir = std::env::temp_dir().join(format!("snpm-lock-{}", std::process::id()));
+        let entry = dir.join("entry");
+        let held = lock_entry(&entry, false).unwrap();
+        assert!(try_lock_entry(&entry).unwrap().is_none());
+        drop(held);
+        assert!(try_lock_entry(&entry).unwrap().is_some());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
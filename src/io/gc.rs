@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use super::cache;
+use crate::utils::config::Config;
+use crate::utils::utils;
+
+/// Size the package cache is pruned down to unless `cache-max-size` says
+/// otherwise.
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+
+/// Days a package may go unused before pruning removes it, unless
+/// `cache-max-unused-days` says otherwise.
+pub const DEFAULT_MAX_UNUSED_DAYS: u64 = 90;
+
+/// The cache directories `prune` manages. Each holds one entry per file or
+/// directory, locked through a `<entry>.lock` file next to it.
+const ENTRY_DIRS: &[&str] = &["_packages", "_tarball", "_file", "_git", "_npx", "_metadata"];
+
+/// `index.json` in the cache directory: when each entry was last used and
+/// how much space it takes, keyed by its path in the cache, such as
+/// `_packages/left-pad@1.3.0`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default, alias = "packages")]
+    entries: BTreeMap<String, IndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    last_used: u64,
+    size: u64,
+}
+
+/// How far `prune` shrinks the cache.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_size: u64,
+    pub max_unused_days: u64,
+}
+
+impl Limits {
+    /// Reads `cache-max-size` (bytes, or with a `KB`, `MB` or `GB` suffix)
+    /// and `cache-max-unused-days`.
+    pub fn from_config(config: &Config) -> Self {
+        Limits {
+            max_size: config
+                .get("cache-max-size")
+                .and_then(parse_size)
+                .unwrap_or(DEFAULT_MAX_SIZE),
+            max_unused_days: config
+                .get("cache-max-unused-days")
+                .and_then(|days| days.parse().ok())
+                .unwrap_or(DEFAULT_MAX_UNUSED_DAYS),
+        }
+    }
+}
+
+/// What `prune` removed and what is left.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    pub removed: usize,
+    pub freed: u64,
+    pub remaining: usize,
+    pub size: u64,
+}
+
+/// Marks the cache entries holding `paths` as used now. A path may point
+/// anywhere inside an entry, such as the contents of a tarball entry.
+/// Entries the index doesn't know yet are measured.
+pub fn record_use(paths: &[&Path]) {
+    let cache_dir = utils::get_cache_directory();
+    let keys: Vec<String> = paths.iter().filter_map(|path| key_of(&cache_dir, path)).collect();
+    if keys.is_empty() {
+        return;
+    }
+
+    let Ok(_lock) = lock_index() else {
+        return;
+    };
+    let mut index = load();
+    let now = cache::now();
+    for key in keys {
+        let path = cache_dir.join(&key);
+        if !path.exists() {
+            continue;
+        }
+        let size = match index.entries.get(&key) {
+            Some(entry) => entry.size,
+            None => size_of(&path),
+        };
+        index.entries.insert(key, IndexEntry { last_used: now, size });
+    }
+    save(&index);
+}
+
+/// Marks the cache entries of `packages` (name and version) as used now.
+pub fn record_packages(packages: &[(String, String)]) {
+    let cache_dir = utils::get_cache_directory();
+    let dirs: Vec<PathBuf> = packages
+        .iter()
+        .map(|(name, version)| cache::package_dir(&cache_dir, name, version))
+        .collect();
+    record_use(&dirs.iter().map(PathBuf::as_path).collect::<Vec<_>>());
+}
+
+/// Removes cache entries unused for longer than `max_unused_days`, then
+/// the least recently used ones until the cache fits in `max_size`.
+/// Entries missing from the index count as last used when they were
+/// written. Temporary files left by killed processes go after a day, and
+/// lock files go with their entries. Entries locked by someone else are in
+/// use and stay: installs take the index lock while holding an entry lock,
+/// so waiting for one here could deadlock.
+pub fn prune(limits: Limits) -> Pruned {
+    let Ok(_lock) = lock_index() else {
+        return Pruned::default();
+    };
+    let mut index = load();
+    let cache_dir = utils::get_cache_directory();
+    let now = cache::now();
+
+    let mut entries: Vec<(String, IndexEntry)> = vec![];
+    for dir in ENTRY_DIRS {
+        let Ok(children) = fs::read_dir(cache_dir.join(dir)) else {
+            continue;
+        };
+        for path in children.flatten().map(|child| child.path()) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            if cache::is_temporary(&path) {
+                if now.saturating_sub(modified(&path)) > 24 * 60 * 60 {
+                    let _ = remove(&path);
+                }
+            } else if let Some(entry) = name.strip_suffix(".lock") {
+                remove_orphaned_lock(&path.with_file_name(entry));
+            } else {
+                let key = format!("{}/{}", dir, name);
+                let entry = index.entries.get(&key).cloned().unwrap_or_else(|| IndexEntry {
+                    last_used: modified(&path),
+                    size: size_of(&path),
+                });
+                entries.push((key, entry));
+            }
+        }
+    }
+    entries.sort_by_key(|(_, entry)| entry.last_used);
+
+    let cutoff = now.saturating_sub(limits.max_unused_days.saturating_mul(24 * 60 * 60));
+    let mut size: u64 = entries.iter().map(|(_, entry)| entry.size).sum();
+    let mut pruned = Pruned::default();
+    index.entries.clear();
+
+    for (key, entry) in entries {
+        if entry.last_used >= cutoff && size <= limits.max_size {
+            index.entries.insert(key, entry);
+            continue;
+        }
+        let path = cache_dir.join(&key);
+        let removed = match cache::try_lock_entry(&path) {
+            Ok(Some(lock)) => remove(&path).map(|()| cache::remove_lock(&path, lock)),
+            Ok(None) => Err(std::io::ErrorKind::WouldBlock.into()),
+            Err(e) => Err(e),
+        };
+        match removed {
+            Ok(()) => {
+                size -= entry.size;
+                pruned.removed += 1;
+                pruned.freed += entry.size;
+            }
+            Err(_) => {
+                index.entries.insert(key, entry);
+            }
+        }
+    }
+
+    pruned.remaining = index.entries.len();
+    pruned.size = size;
+    save(&index);
+    pruned
+}
+
+/// Removes the lock file of `entry` if the entry itself is gone.
+fn remove_orphaned_lock(entry: &Path) {
+    if entry.exists() {
+        return;
+    }
+    if let Ok(Some(lock)) = cache::try_lock_entry(entry) {
+        if !entry.exists() {
+            cache::remove_lock(entry, lock);
+        }
+    }
+}
+
+/// Parses a size such as `500MB`, `10GB` or a number of bytes.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim().to_uppercase();
+    let (number, multiplier) = [("GB", 1024 * 1024 * 1024), ("MB", 1024 * 1024), ("KB", 1024), ("B", 1)]
+        .iter()
+        .find_map(|(suffix, multiplier)| size.strip_suffix(suffix).map(|number| (number.to_string(), *multiplier)))
+        .unwrap_or((size.clone(), 1));
+    let number: f64 = number.trim().parse().ok()?;
+    (number >= 0.0).then_some((number * multiplier as f64) as u64)
+}
+
+/// Formats a number of bytes for people.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 * 1024 => format!("{:.1} GB", b as f64 / (1024.0 * 1024.0 * 1024.0)),
+        b if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
+
+fn index_path() -> PathBuf {
+    utils::get_cache_directory().join("index.json")
+}
+
+fn load() -> Index {
+    fs::read(index_path())
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+        .unwrap_or_default()
+}
+
+/// The index only speeds up pruning, so failing to write it is not fatal.
+fn save(index: &Index) {
+    if let Ok(content) = serde_json::to_vec_pretty(index) {
+        let _ = cache::write_atomic(&index_path(), &content);
+    }
+}
+
+/// Serializes updates of the index between snpm processes.
+fn lock_index() -> std::io::Result<fs::File> {
+    cache::lock_entry(&index_path(), true)
+}
+
+/// The index key of the entry `path` is in: its first two components
+/// below the cache directory.
+fn key_of(cache_dir: &Path, path: &Path) -> Option<String> {
+    let mut components = path.strip_prefix(cache_dir).ok()?.components();
+    let dir = components.next()?.as_os_str().to_string_lossy().to_string();
+    let entry = components.next()?.as_os_str().to_string_lossy().to_string();
+    ENTRY_DIRS.contains(&dir.as_str()).then(|| format!("{}/{}", dir, entry))
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::metadata(path.join("entry.json"))
+        .or_else(|_| fs::metadata(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn size_of(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(kind) if kind.is_dir() => size_of(&entry.path()),
+            Ok(_) => entry.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("2KB"), Some(2048));
+        assert_eq!(parse_size("500MB"), Some(500 * 1024 * 1024));
+        assert_eq!(parse_size("10GB"), Some(10 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size(" 1.5 gb "), Some(1536 * 1024 * 1024));
+        assert_eq!(parse_size("0"), Some(0));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("GB"), None);
+        assert_eq!(parse_size("-1MB"), None);
+        assert_eq!(parse_size("lots"), None);
+        assert_eq!(parse_size("NaN"), None);
+        assert_eq!(parse_size("10TB"), None);
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(500 * 1024 * 1024), "500.0 MB");
+        assert_eq!(format_size(10 * 1024 * 1024 * 1024), "10.0 GB");
+    }
+}
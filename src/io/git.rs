@@ -0,0 +1,141 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use semver::Version;
+use tar::Archive;
+use super::cache::{self, CacheMode};
+use super::{gc, logger};
+use crate::utils::{range, utils};
+
+/// Checks out `url` at `committish` into the snpm cache using the local git
+/// binary, returning the checkout and the commit it is at. A missing
+/// committish means the default branch, and `semver:<range>` picks the
+/// highest matching tag. With `--offline` the mirror is never fetched, and
+/// with `--prefer-offline` only when it doesn't know `committish` yet.
+/// The mirror is locked while in use, so concurrent installs take turns
+/// fetching and extracting the same repository.
+pub fn checkout(
+    url: &str,
+    committish: Option<&str>,
+    mode: CacheMode,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    let git_dir = utils::get_cache_directory().join("_git");
+    let key = cache_key(url);
+    let mirror = git_dir.join(format!("{}.git", key));
+    let _lock = cache::lock_entry(&mirror, true)?;
+
+    if !mirror.exists() {
+        if mode == CacheMode::Offline {
+            return Err(CacheMode::offline_error(url).into());
+        }
+        logger::info(&format!("Cloning {}", url));
+        cache::fill_entry(&mirror, |temp| {
+            git(&git_dir, &["clone", "--mirror", "--quiet", url, &temp.to_string_lossy()]).map(|_| ())
+        })?;
+    } else if mode != CacheMode::Offline
+        && !committish.is_some_and(|c| is_commit(&mirror, c))
+        && !(mode == CacheMode::PreferOffline && is_known(&mirror, committish))
+    {
+        git(&mirror, &["fetch", "--quiet", "--prune", "--tags"])?;
+    }
+
+    let target = match committish {
+        None => "HEAD".to_string(),
+        Some(committish) => match committish.strip_prefix("semver:") {
+            Some(range) => highest_tag(&mirror, range)?
+                .ok_or_else(|| format!("No tag of {} matches {}", url, range))?,
+            None => committish.to_string(),
+        },
+    };
+
+    let commit = git(&mirror, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", target)])
+        .map_err(|_| format!("Could not find '{}' in {}", target, url))?
+        .trim()
+        .to_string();
+
+    let checkout = git_dir.join(format!("{}-{}", key, commit));
+    if !checkout.exists() {
+        let archive = Command::new("git")
+            .args(["archive", "--format=tar", &commit])
+            .current_dir(&mirror)
+            .output()?;
+        if !archive.status.success() {
+            return Err(format!(
+                "git archive failed for {}: {}",
+                url,
+                String::from_utf8_lossy(&archive.stderr).trim()
+            )
+            .into());
+        }
+
+        cache::fill_entry(&checkout, |temp| {
+            Archive::new(Cursor::new(archive.stdout)).unpack(temp)?;
+            Ok(())
+        })?;
+    }
+
+    gc::record_use(&[&mirror, &checkout]);
+    Ok((checkout, commit))
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Whether `committish` is a full commit hash already present in `mirror`,
+/// in which case there is nothing to fetch.
+fn is_commit(mirror: &Path, committish: &str) -> bool {
+    committish.len() == 40
+        && committish.chars().all(|c| c.is_ascii_hexdigit())
+        && git(mirror, &["cat-file", "-e", &format!("{}^{{commit}}", committish)]).is_ok()
+}
+
+/// Whether the mirror can already resolve `committish`: a branch, tag or
+/// commit it has, or a `semver:` range one of its tags satisfies.
+fn is_known(mirror: &Path, committish: Option<&str>) -> bool {
+    match committish {
+        None => true,
+        Some(committish) => match committish.strip_prefix("semver:") {
+            Some(range) => highest_tag(mirror, range).is_ok_and(|tag| tag.is_some()),
+            None => git(mirror, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", committish)]).is_ok(),
+        },
+    }
+}
+
+fn highest_tag(mirror: &Path, range: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let tags = git(mirror, &["tag", "--list"])?;
+
+    Ok(tags
+        .lines()
+        .filter_map(|tag| {
+            let version = Version::parse(tag.trim_start_matches('v')).ok()?;
+            range::satisfies(&version.to_string(), range).then(|| (version, tag.to_string()))
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag))
+}
+
+fn cache_key(url: &str) -> String {
+    url.trim_end_matches(".git")
+        .split("://")
+        .last()
+        .unwrap_or(url)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
@@ -26,7 +26,7 @@ fn format_message(message: String, log_type: &str) -> String {
         "info" => format!("{} {} {}", colorize("white", "snpm"), colorize("light_blue", "info"), message),
         "warn" => format!("{} {} {}", colorize("white", "snpm"), colorize("yellow", "warn"), message),
         "error" => format!("{} {} {}", colorize("white", "snpm"), colorize("red", "err"), message),
-        _ => format!("{} {}", "Unknown color".to_string(), message),
+        _ => format!("{} {}", "Unknown color", message),
     }
 }
 
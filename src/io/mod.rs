@@ -1,2 +1,5 @@
 pub mod net;
 pub mod logger;
+pub mod git;
+pub mod cache;
+pub mod gc;
@@ -1,55 +1,413 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::io::copy;
-use std::path::Path;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+use bytes::Bytes;
 use flate2::read::GzDecoder;
-use reqwest::blocking::Client;
-use semver::{Version, VersionReq};
+use reqwest::header::{HeaderValue, ACCEPT, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tar::Archive;
-use super::logger;
-
-pub fn download_and_cache_package(
-    path: &Path,
-    debug_mode: bool,
-    force_mode: bool,
-) -> Result<(String, String), Box<dyn std::error::Error>> {
-    if path.exists() {
-        fs::remove_dir_all(path)?;
-    }
-    let temp_tarball = path.with_extension("tgz");
-    if temp_tarball.exists() {
-        fs::remove_file(&temp_tarball)?;
-    }
-    let temp_extract_dir = path.with_extension("tmp");
-    if temp_extract_dir.exists() {
-        fs::remove_dir_all(&temp_extract_dir)?;
-    }
-
-    let package_name = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or("Invalid package name")?;
-
-    let parts: Vec<&str> = package_name.split('_').collect();
-    if parts.len() != 2 {
-        return Err("Invalid package name format".into());
-    }
-    let (name, version_req_str) = (parts[0], parts[1].trim_matches('"'));
-
-    let registry_url = if name.starts_with("@angular/") {
-        let package_name = name.replace('@', "").replace('/', "%2F");
-        format!("https://registry.npmjs.org/{}", package_name)
-    } else if name.starts_with('@') {
-        let encoded_name = name.replace('@', "").replace('/', "%2F");
-        format!("https://registry.npmjs.org/{}", encoded_name)
-    } else {
-        format!("https://registry.npmjs.org/{}", name)
+use tokio::runtime::Runtime;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use super::{cache, logger};
+use super::cache::CacheMode;
+use crate::utils::config::Config;
+use crate::utils::utils;
+
+/// How many registry requests run at once unless `network-concurrency`
+/// says otherwise.
+pub const DEFAULT_NETWORK_CONCURRENCY: usize = 16;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// How often and how patiently failed requests are retried, configured
+/// with npm's `fetch-retries`, `fetch-retry-factor`,
+/// `fetch-retry-mintimeout` and `fetch-retry-maxtimeout`, and how long a
+/// single request may take (`fetch-timeout`, where 0 means no limit).
+/// Times are in milliseconds.
+#[derive(Clone, Copy)]
+struct Retry {
+    retries: u32,
+    factor: u32,
+    min_timeout: Duration,
+    max_timeout: Duration,
+    timeout: Option<Duration>,
+}
+
+impl Retry {
+    fn from_config(config: &Config) -> Self {
+        let number = |key: &str, default: u64| config.get(key).and_then(|value| value.parse().ok()).unwrap_or(default);
+        let count = |key: &str, default: u64| u32::try_from(number(key, default)).unwrap_or(u32::MAX);
+        Retry {
+            retries: count("fetch-retries", 2),
+            factor: count("fetch-retry-factor", 10),
+            min_timeout: Duration::from_millis(number("fetch-retry-mintimeout", 10_000)),
+            max_timeout: Duration::from_millis(number("fetch-retry-maxtimeout", 60_000)),
+            timeout: Some(number("fetch-timeout", 300_000))
+                .filter(|&timeout| timeout > 0)
+                .map(Duration::from_millis),
+        }
+    }
+
+    /// How long to wait before the attempt after `attempt`, less a random
+    /// part of up to half so that parallel requests don't retry in lockstep.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.delay(attempt);
+        let jitter = (RandomState::new().build_hasher().finish() % 1000) as u32;
+        delay - delay / 2000 * jitter
+    }
+
+    /// [`Retry::backoff`] without the jitter: `min_timeout`, growing by
+    /// `factor` with each attempt, up to `max_timeout`.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.min_timeout
+            .saturating_mul(self.factor.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_timeout)
+    }
+
+    /// The wait a `Retry-After` header asks for, in seconds or as a date,
+    /// capped at `max_timeout`.
+    fn retry_after(&self, response: &Response) -> Option<Duration> {
+        let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+        self.parse_retry_after(value, SystemTime::now())
+    }
+
+    fn parse_retry_after(&self, value: &str, now: SystemTime) -> Option<Duration> {
+        let value = value.trim();
+        let delay = match value.parse() {
+            Ok(seconds) => Duration::from_secs(seconds),
+            Err(_) => httpdate::parse_http_date(value)
+                .ok()?
+                .duration_since(now)
+                .unwrap_or_default(),
+        };
+        Some(delay.min(self.max_timeout))
+    }
+}
+
+/// The HTTP client all registry traffic goes through. There is one per
+/// process, so connections are pooled and reused (over HTTP/2 where the
+/// registry offers it), and at most `network-concurrency` requests are in
+/// flight at a time no matter how many threads make them.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    runtime: Arc<Runtime>,
+    permits: Arc<Semaphore>,
+    concurrency: usize,
+    retry: Retry,
+    /// The configured registry, ending in a slash.
+    registry: String,
+    cache_dir: PathBuf,
+    metadata_max_age: u64,
+}
+
+impl Client {
+    fn from_config(config: &Config) -> Self {
+        let concurrency = config
+            .get("network-concurrency")
+            .and_then(|value| value.parse().ok())
+            .filter(|&value| value > 0)
+            .unwrap_or(DEFAULT_NETWORK_CONCURRENCY);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("snpm-net")
+            .build()
+            .expect("Failed to start the network runtime");
+        let retry = Retry::from_config(config);
+        let mut http = reqwest::Client::builder().pool_max_idle_per_host(concurrency);
+        if let Some(timeout) = retry.timeout {
+            http = http.timeout(timeout);
+        }
+        let http = http.build().expect("Failed to create the HTTP client");
+
+        Client {
+            http,
+            runtime: Arc::new(runtime),
+            permits: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+            retry,
+            registry: config.registry(),
+            cache_dir: utils::cache_directory(config),
+            metadata_max_age: config
+                .get("cache-max-age")
+                .and_then(|age| age.parse().ok())
+                .unwrap_or(cache::DEFAULT_METADATA_MAX_AGE),
+        }
+    }
+
+    /// Registry URL for a package's metadata document, encoding the slash
+    /// of scoped names.
+    pub fn registry_url(&self, name: &str) -> String {
+        format!("{}{}", self.registry, name.replace('/', "%2F"))
+    }
+
+    /// The cache directory the client was configured with.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// How many requests may be in flight at once.
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Runs `future` on the network runtime, blocking the calling thread
+    /// until it completes. Must not be called from inside the runtime.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Waits for one of the `network-concurrency` request slots.
+    async fn permit(&self) -> OwnedSemaphorePermit {
+        self.permits.clone().acquire_owned().await.expect("The request semaphore is never closed")
+    }
+
+    /// Sends the request `build` makes for `url`, retrying with backoff on
+    /// connection errors, timeouts, 429 and 5xx responses. Other responses
+    /// are returned as they are, along with the request slot they hold.
+    async fn send(
+        &self,
+        url: &str,
+        build: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> Result<(Response, OwnedSemaphorePermit), Error> {
+        let attempts = self.retry.retries + 1;
+        let mut attempt = 1;
+        loop {
+            let permit = self.permit().await;
+            let (reason, delay) = match build(&self.http).send().await {
+                Ok(response) if is_transient(response.status()) => {
+                    (format!("status {}", response.status()), self.retry.retry_after(&response))
+                }
+                Ok(response) => return Ok((response, permit)),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => (e.to_string(), None),
+                Err(e) => return Err(format!("Failed to fetch {}: {}", url, e).into()),
+            };
+            drop(permit);
+
+            if attempt == attempts {
+                return Err(format!(
+                    "Failed to fetch {} after {} attempt{}: {}",
+                    url,
+                    attempts,
+                    if attempts == 1 { "" } else { "s" },
+                    reason
+                )
+                    .into());
+            }
+            let delay = delay.unwrap_or_else(|| self.retry.backoff(attempt));
+            logger::warn(&format!(
+                "Request to {} failed ({}), retrying in {:.1}s ({} of {} attempts)",
+                url,
+                reason,
+                delay.as_secs_f64(),
+                attempt,
+                attempts
+            ));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether a response is worth retrying: the registry is rate limiting or
+/// having trouble.
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// The process-wide client, created from the configuration on first use.
+pub fn client() -> Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| Client::from_config(&Config::load())).clone()
+}
+
+/// Asks for the abbreviated ("corgi") metadata documents npm installs with,
+/// accepting full ones from registries that don't have them.
+const ABBREVIATED_ACCEPT: &str = "application/vnd.npm.install-v1+json; q=1.0, application/json; q=0.8, */*";
+
+/// The parts of a packument snpm installs from. Full documents are parsed
+/// into this too, which drops readmes, maintainers and the like.
+#[derive(Deserialize, Serialize)]
+struct AbbreviatedPackument {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "dist-tags", default)]
+    dist_tags: BTreeMap<String, String>,
+    #[serde(default)]
+    versions: BTreeMap<String, AbbreviatedVersion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AbbreviatedVersion {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    optional_dependencies: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer_dependencies: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer_dependencies_meta: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bundle_dependencies: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bundled_dependencies: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bin: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    directories: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    engines: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    os: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cpu: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    libc: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deprecated: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    has_install_script: Option<bool>,
+    #[serde(default)]
+    dist: Value,
+}
+
+/// Fetches the packument of `name`. Packuments are kept in the snpm cache
+/// and used as they are for `cache-max-age` seconds; after that the registry
+/// is asked with `If-None-Match`/`If-Modified-Since` whether they changed.
+/// When the registry can't be reached, a cached packument of any age is
+/// used instead, as it always is with `--prefer-offline` and `--offline`.
+pub fn fetch_metadata(client: &Client, name: &str, mode: CacheMode) -> Result<Value, Box<dyn std::error::Error>> {
+    client.block_on(fetch_metadata_async(client, name, mode)).map_err(|e| e as Box<dyn std::error::Error>)
+}
+
+/// Fetches the packuments of `wanted` (names with the range they're needed
+/// at) concurrently and returns them with each failure as a message. As
+/// each packument arrives, `follow` is given it and the range and names
+/// the packages that will be needed next, usually the dependencies of the
+/// version the range selects. Those are requested straight away, so a whole
+/// dependency graph is fetched in about as many round trips as it is deep,
+/// with every request in flight as soon as it is known.
+pub fn fetch_metadata_pipelined<F>(
+    client: &Client,
+    wanted: Vec<(String, String)>,
+    mode: CacheMode,
+    mut follow: F,
+) -> Vec<(String, Result<Value, String>)>
+where
+    F: FnMut(&Value, &str) -> Vec<(String, String)>,
+{
+    client.block_on(async {
+        let mut requested = HashSet::new();
+        let mut tasks = JoinSet::new();
+        let mut request = |tasks: &mut JoinSet<_>, name: String, range: String| {
+            if !requested.insert(name.clone()) {
+                return;
+            }
+            let client = client.clone();
+            tasks.spawn(async move {
+                let result = fetch_metadata_async(&client, &name, mode).await.map_err(|e| e.to_string());
+                (name, range, result)
+            });
+        };
+        for (name, range) in wanted {
+            request(&mut tasks, name, range);
+        }
+
+        let mut fetched = vec![];
+        while let Some(joined) = tasks.join_next().await {
+            let Ok((name, range, result)) = joined else {
+                logger::error("A metadata request panicked");
+                continue;
+            };
+            if let Ok(metadata) = &result {
+                for (next, next_range) in follow(metadata, &range) {
+                    request(&mut tasks, next, next_range);
+                }
+            }
+            fetched.push((name, result));
+        }
+        fetched
+    })
+}
+
+async fn fetch_metadata_async(client: &Client, name: &str, mode: CacheMode) -> Result<Value, Error> {
+    let url = client.registry_url(name);
+    let cached = cache::load_metadata(&client.cache_dir, &url);
+    if let Some(cached) = cached
+        .as_ref()
+        .filter(|cached| mode.trusts_cache() || cached.age() < client.metadata_max_age)
+    {
+        return Ok(cached.packument.clone());
+    }
+    if mode == CacheMode::Offline {
+        return Err(CacheMode::offline_error(&format!("Metadata for '{}'", name)).into());
+    }
+
+    logger::info(&format!("Fetching metadata from: {}", url));
+
+    let send = |accept: &'static str| {
+        let (url, cached) = (&url, &cached);
+        client.send(url, move |http| {
+            let mut request = http.get(url).header(ACCEPT, accept);
+            if let Some(cached) = cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            request
+        })
     };
 
-    logger::info(&format!("Fetching metadata from: {}", registry_url));
+    // Registries that can't serve abbreviated documents either ignore the
+    // preference or, rarely, refuse it outright.
+    let response = match send(ABBREVIATED_ACCEPT).await {
+        Ok((response, permit)) if response.status() == StatusCode::NOT_ACCEPTABLE => {
+            drop(permit);
+            send("application/json").await
+        }
+        result => result,
+    };
+    let (response, _permit) = match response {
+        Ok(sent) => sent,
+        Err(e) => {
+            return match cached {
+                Some(cached) => {
+                    logger::warn(&format!("Using cached metadata for '{}' ({})", name, e));
+                    Ok(cached.packument)
+                }
+                None => Err(format!("Failed to fetch package metadata for '{}': {}", name, e).into()),
+            };
+        }
+    };
 
-    let client = Client::new();
-    let response = client.get(&registry_url).send()?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(mut cached) = cached {
+            cached.touch();
+            if let Err(e) = cache::store_metadata(&client.cache_dir, &url, &cached) {
+                logger::warn(&format!("Failed to update cached metadata for '{}': {}", name, e));
+            }
+            return Ok(cached.packument);
+        }
+    }
 
     if !response.status().is_success() {
         return Err(format!(
@@ -60,130 +418,351 @@ pub fn download_and_cache_package(
             .into());
     }
 
-    let metadata: Value = response.json()?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(String::from)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+    let packument = serde_json::to_value(response.json::<AbbreviatedPackument>().await?)?;
 
-    let versions_obj = metadata
-        .get("versions")
-        .and_then(|v| v.as_object())
-        .ok_or_else(|| format!("No versions found in metadata for package '{}'", name))?;
+    let cached = cache::CachedMetadata::new(packument, etag, last_modified);
+    if let Err(e) = cache::store_metadata(&client.cache_dir, &url, &cached) {
+        logger::warn(&format!("Failed to cache metadata for '{}': {}", name, e));
+    }
+    Ok(cached.packument)
+}
 
-    let mut available_versions = Vec::new();
-    for version in versions_obj.keys() {
-        if let Ok(v) = Version::parse(version) {
-            available_versions.push(v);
+/// Downloads `url`, handing the body to `consume` as it arrives. When the
+/// connection drops or times out partway through, the download starts over
+/// after a backoff, so `consume` may be called more than once.
+pub fn download<T>(
+    client: &Client,
+    url: &str,
+    mut consume: impl FnMut(&mut Download) -> Result<T, Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let attempts = client.retry.retries + 1;
+    let mut attempt = 1;
+    loop {
+        let mut body = open(client, url)?;
+        let result = consume(&mut body);
+        let interrupted = body.interrupted.take();
+        drop(body);
+
+        match (result, interrupted) {
+            (Err(_), Some(reason)) if attempt < attempts => {
+                let delay = client.retry.backoff(attempt);
+                logger::warn(&format!(
+                    "Download of {} failed ({}), retrying in {:.1}s ({} of {} attempts)",
+                    url,
+                    reason,
+                    delay.as_secs_f64(),
+                    attempt,
+                    attempts
+                ));
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            (Err(_), Some(reason)) => {
+                return Err(format!("Failed to download {} after {} attempts: {}", url, attempts, reason).into())
+            }
+            (result, _) => return result,
         }
     }
+}
 
-    if available_versions.is_empty() {
-        return Err(format!("No valid versions found for package '{}'", name).into());
+/// Starts downloading `url`, failing on error statuses. The body is only
+/// read as the download is consumed, and the download keeps its request
+/// slot until then.
+fn open(client: &Client, url: &str) -> Result<Download, Box<dyn std::error::Error>> {
+    let (response, permit) = client
+        .block_on(client.send(url, |http| http.get(url)))
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to download {}. Status: {}",
+            url,
+            response.status()
+        )
+            .into());
     }
+    Ok(Download {
+        client: client.clone(),
+        response,
+        chunk: Bytes::new(),
+        interrupted: None,
+        _permit: permit,
+    })
+}
 
-    available_versions.sort_by(|a, b| b.cmp(a));
+/// A response body being downloaded, read synchronously chunk by chunk as
+/// it arrives.
+pub struct Download {
+    client: Client,
+    response: Response,
+    chunk: Bytes,
+    /// Why the body stopped arriving, if it did.
+    interrupted: Option<String>,
+    _permit: OwnedSemaphorePermit,
+}
 
-    if debug_mode {
-        logger::info(&format!("Available versions for {}:", name));
-        for version in &available_versions {
-            logger::info(&format!("  - {}", version));
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.client.block_on(self.response.chunk()) {
+                Ok(Some(chunk)) => self.chunk = chunk,
+                Ok(None) => return Ok(0),
+                Err(e) => {
+                    self.interrupted = Some(e.to_string());
+                    return Err(io::Error::other(e));
+                }
+            }
         }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
     }
+}
 
-    let clean_version_req = version_req_str
-        .trim_start_matches('^')
-        .trim_start_matches('~');
-    let version_req = VersionReq::parse(&format!("^{}", clean_version_req))
-        .or_else(|_| VersionReq::parse(&format!("={}", clean_version_req)))?;
-
-    logger::info(&format!("Looking for version matching: {}", version_req));
+/// Streams a gzipped package tarball into `destination`, dropping the
+/// top-level directory (usually `package/`) from every path. Like npm,
+/// only files and directories are extracted: a symlink or hard link could
+/// otherwise point a later entry outside `destination`. All of `reader`
+/// is consumed, so a reader that hashes what passes through sees the
+/// whole tarball.
+pub fn unpack_tarball(reader: impl Read, source: &str, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let failed = |e: std::io::Error| format!("Failed to extract {}: {}", source, e);
+    fs::create_dir_all(destination)?;
+
+    let mut archive = Archive::new(GzDecoder::new(reader));
+    let mut unpacked = false;
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_dir() {
+            continue;
+        }
+        let path = entry.path().map_err(failed)?.into_owned();
+        let relative: PathBuf = path.components().skip(1).collect();
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+        if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("Refusing to extract {} from {}", path.display(), source).into());
+        }
 
-    let selected_version = match available_versions.iter().find(|v| version_req.matches(v)) {
-        Some(v) => {
-            logger::info(&format!(
-                "Selected version {} (requested {})",
-                v, version_req_str
-            ));
-            v
+        let target = destination.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
         }
-        None => {
-            if !force_mode {
-                logger::error(&format!(
-                    "No version found matching {} for package '{}'. Use --force to install closest available version.",
-                    version_req, name
-                ));
-                return Err(format!("Version not found for package '{}'", name).into());
-            }
+        entry.unpack(&target).map_err(failed)?;
+        unpacked = true;
+    }
+    if !unpacked {
+        return Err(format!("No package directory found in archive {}", source).into());
+    }
 
-            let req_version = Version::parse(clean_version_req)?;
-            let closest_version = available_versions
-                .iter()
-                .min_by_key(|v| (v.major as i64 - req_version.major as i64).abs())
-                .ok_or_else(|| format!("No versions available for package '{}'", name))?;
+    // Read past the end of the tar data and the gzip trailer.
+    let mut decoder = archive.into_inner();
+    io::copy(&mut decoder, &mut io::sink()).map_err(failed)?;
+    io::copy(&mut decoder.into_inner(), &mut io::sink()).map_err(failed)?;
 
-            logger::warn(&format!(
-                "Using closest available version {} for package {} (requested {})",
-                closest_version, name, version_req
-            ));
-            closest_version
+    Ok(())
+}
+
+/// Uploads a publish document for `name`, as built by `snpm publish`.
+pub fn publish_package(
+    client: &Client,
+    name: &str,
+    document: &Value,
+    token: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = client.registry_url(name);
+    let mut request = client.http.put(&url).json(document);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    client.block_on(async {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Registry responded with {}: {}", status, body.trim()).into());
         }
-    };
+        Ok(())
+    })
+}
 
-    let version_data = &metadata["versions"][&selected_version.to_string()];
-    let tarball_url = version_data["dist"]["tarball"]
-        .as_str()
-        .ok_or_else(|| format!("Failed to get tarball URL for package '{}'", name))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    logger::info(&format!(
-        "Downloading {} from {}",
-        package_name, tarball_url
-    ));
+    fn with_settings(settings: &[(&str, &str)]) -> Retry {
+        let mut config = Config::default();
+        for (key, value) in settings {
+            config.set(key, value);
+        }
+        Retry::from_config(&config)
+    }
 
-    let tarball_response = client.get(tarball_url).send()?;
+    #[test]
+    fn backs_off_by_the_factor_up_to_the_maximum() {
+        let retry = with_settings(&[]);
+        assert_eq!(retry.delay(1), Duration::from_secs(10));
+        assert_eq!(retry.delay(2), Duration::from_secs(60));
+        assert_eq!(retry.delay(3), Duration::from_secs(60));
 
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+        let retry = with_settings(&[("fetch-retry-factor", "2"), ("fetch-retry-mintimeout", "100")]);
+        let schedule: Vec<u128> = (1..=4).map(|attempt| retry.delay(attempt).as_millis()).collect();
+        assert_eq!(schedule, [100, 200, 400, 800]);
     }
 
-    let mut temp_file = fs::File::create(&temp_tarball)?;
+    #[test]
+    fn a_zero_fetch_timeout_means_no_limit() {
+        assert_eq!(with_settings(&[]).timeout, Some(Duration::from_secs(300)));
+        assert_eq!(with_settings(&[("fetch-timeout", "1500")]).timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(with_settings(&[("fetch-timeout", "0")]).timeout, None);
+    }
 
-    copy(&mut tarball_response.bytes()?.as_ref(), &mut temp_file)?;
+    #[test]
+    fn jitter_takes_off_at_most_half() {
+        let retry = with_settings(&[]);
+        for attempt in 1..=3 {
+            let delay = retry.delay(attempt);
+            let backoff = retry.backoff(attempt);
+            assert!(backoff <= delay && backoff >= delay / 2, "{:?} of {:?}", backoff, delay);
+        }
+    }
 
-    fs::create_dir_all(&temp_extract_dir)?;
+    #[test]
+    fn huge_settings_saturate_instead_of_overflowing() {
+        let max = u64::MAX.to_string();
+        let retry = with_settings(&[
+            ("fetch-retry-factor", &max),
+            ("fetch-retry-mintimeout", &max),
+            ("fetch-retry-maxtimeout", &max),
+        ]);
+        assert_eq!(retry.factor, u32::MAX);
+        for attempt in [0, 1, 2, 100, u32::MAX] {
+            assert_eq!(retry.delay(attempt), Duration::from_millis(u64::MAX));
+            assert!(retry.backoff(attempt) >= Duration::from_millis(u64::MAX / 2));
+        }
 
-    let tar_gz = fs::File::open(&temp_tarball)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
+        let retry = with_settings(&[("fetch-retry-factor", &max), ("fetch-retry-maxtimeout", "30000")]);
+        assert_eq!(retry.delay(u32::MAX), Duration::from_secs(30));
+    }
 
-    archive.unpack(&temp_extract_dir)?;
+    #[test]
+    fn parses_retry_after_seconds() {
+        let retry = with_settings(&[]);
+        let now = SystemTime::now();
+        assert_eq!(retry.parse_retry_after("5", now), Some(Duration::from_secs(5)));
+        assert_eq!(retry.parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(retry.parse_retry_after("86400", now), Some(Duration::from_secs(60)));
+        assert_eq!(retry.parse_retry_after("soon", now), None);
+        assert_eq!(retry.parse_retry_after("-1", now), None);
+    }
 
-    let package_dir = fs::read_dir(&temp_extract_dir)?
-        .filter_map(Result::ok)
-        .find(|entry| entry.path().is_dir())
-        .ok_or_else(|| format!("No package directory found in archive for '{}'", name))?;
+    #[test]
+    fn parses_retry_after_dates() {
+        let retry = with_settings(&[]);
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            retry.parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(retry.parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(
+            retry.parse_retry_after("Thu, 22 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+    }
 
-    fs::create_dir_all(path)?;
+    /// A gzipped tarball built by `build`, which appends its entries.
+    fn tarball(build: impl FnOnce(&mut tar::Builder<Vec<u8>>)) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        build(&mut builder);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, &builder.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
 
-    for entry in fs::read_dir(package_dir.path())? {
-        let entry = entry?;
-        let target_path = path.join(entry.file_name());
-        if entry.path().is_dir() {
-            if target_path.exists() {
-                fs::remove_dir_all(&target_path)?;
-            }
-            fs::rename(entry.path(), target_path)?;
-        } else {
-            if target_path.exists() {
-                fs::remove_file(&target_path)?;
-            }
-            fs::copy(entry.path(), target_path)?;
+    fn append(builder: &mut tar::Builder<Vec<u8>>, kind: tar::EntryType, path: &str, link: Option<&Path>, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(0o755);
+        header.set_size(data.len() as u64);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
         }
+        builder.append_data(&mut header, path, data).unwrap();
     }
 
-    fs::remove_dir_all(&temp_extract_dir)?;
-    fs::remove_file(&temp_tarball)?;
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snpm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn unpacks_below_the_top_level_directory() {
+        let dir = scratch("unpack");
+        let data = tarball(|builder| {
+            append(builder, tar::EntryType::Directory, "package/lib", None, b"");
+            append(builder, tar::EntryType::Regular, "package/package.json", None, b"{}");
+            append(builder, tar::EntryType::Regular, "package/lib/index.js", None, b"1");
+        });
+        unpack_tarball(data.as_slice(), "test.tgz", &dir.join("out")).unwrap();
+        assert_eq!(fs::read(dir.join("out/package.json")).unwrap(), b"{}");
+        assert_eq!(fs::read(dir.join("out/lib/index.js")).unwrap(), b"1");
+    }
 
-    logger::info(&format!(
-        "Package cached at {}",
-        path.to_str().unwrap_or("unknown location")
-    ));
+    #[test]
+    fn never_writes_through_links() {
+        let dir = scratch("links");
+        let victim = dir.join("victim");
+        fs::create_dir_all(&victim).unwrap();
+        fs::write(victim.join("existing"), "keep").unwrap();
+        let data = tarball(|builder| {
+            append(builder, tar::EntryType::Regular, "package/package.json", None, b"{}");
+            append(builder, tar::EntryType::Symlink, "package/d", Some(&victim), b"");
+            append(builder, tar::EntryType::Regular, "package/d/x", None, b"pwned");
+            append(builder, tar::EntryType::Link, "package/hard", Some(&victim.join("existing")), b"");
+        });
+
+        let out = dir.join("out");
+        let _ = unpack_tarball(data.as_slice(), "evil.tgz", &out);
+        assert!(!victim.join("x").exists());
+        assert_eq!(fs::read_to_string(victim.join("existing")).unwrap(), "keep");
+        assert!(!out.join("hard").exists());
+        assert!(!out.join("d").symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()));
+    }
 
-    Ok((selected_version.to_string(), tarball_url.to_string()))
+    #[test]
+    fn refuses_paths_leaving_the_destination() {
+        let dir = scratch("escape");
+        let mut data = Vec::new();
+        {
+            // `append_data` refuses `..` itself, so write the name by hand.
+            let mut builder = tar::Builder::new(&mut data);
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(5);
+            header.as_gnu_mut().unwrap().name[..17].copy_from_slice(b"package/../escape");
+            header.set_cksum();
+            builder.append(&header, &b"pwned"[..]).unwrap();
+            builder.finish().unwrap();
+        }
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut encoder, &data).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert!(unpack_tarball(data.as_slice(), "evil.tgz", &dir.join("out")).is_err());
+        assert!(!dir.join("escape").exists());
+    }
 }
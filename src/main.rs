@@ -3,7 +3,7 @@ mod runtime;
 mod utils;
 
 use std::env;
-use crate::runtime::{task, package};
+use crate::runtime::{task, package, npx, create, init, publish, cache};
 use crate::io::logger;
 use crate::utils::info;
 
@@ -17,29 +17,86 @@ fn main() {
 
     let command = &args[1];
     match command.as_str() {
-        "install" => package::install(false, false),
+        "install" => package::install(&package::InstallOptions {
+            debug_mode: has_flag(&args, &["--debug"]),
+            force_mode: has_flag(&args, &["--force", "-f"]),
+            legacy_peer_deps: has_flag(&args, &["--legacy-peer-deps"]),
+            strict_peer_deps: has_flag(&args, &["--strict-peer-deps"]),
+            engine_strict: has_flag(&args, &["--engine-strict"]),
+            offline: has_flag(&args, &["--offline"]),
+            prefer_offline: has_flag(&args, &["--prefer-offline"]),
+            os: flag_value(&args, "--os").map(String::from),
+            cpu: flag_value(&args, "--cpu").map(String::from),
+            libc: flag_value(&args, "--libc").map(String::from),
+        }),
         "task" => {
+            let task_names: Vec<String> = args[2..]
+                .iter()
+                .enumerate()
+                .filter(|(i, arg)| !arg.starts_with('-') && args[i + 1] != "--filter")
+                .map(|(_, arg)| arg.clone())
+                .collect();
+            let filters = flag_values(&args, "--filter");
+            if task_names.is_empty() {
+                task::list(has_flag(&args, &["--json"]));
+                return;
+            }
+
+            let parallel = has_flag(&args, &["--parallel", "-p"]);
+            let sequential = has_flag(&args, &["--sequential", "-s"]);
+            let continue_on_error = has_flag(&args, &["--continue-on-error", "-c"]);
+
+            if !filters.is_empty() || has_flag(&args, &["--workspaces", "-ws"]) {
+                task::run_workspaces(&task_names, &filters, parallel, continue_on_error);
+            } else if task_names.len() == 1 && !task_names[0].contains('*') && !parallel && !sequential {
+                task::run(&task_names[0]);
+            } else {
+                task::run_many(&task_names, parallel, continue_on_error);
+            }
+        },
+        "exec" => {
             if args.len() < 3 {
-                logger::error("No task name provided");
+                logger::error("No command provided for exec");
                 return;
             }
-            task::run(&args[2]);
+            task::exec(&args[2..]);
         },
-        "x" | "exec" => {
+        "x" => {
             if args.len() < 3 {
                 logger::error("No package name provided for execution");
                 return;
             }
-            let package_args = args[2..].join(" ");
-            task::run_npx(&package_args);
+            npx::run(&args[2..]);
+        },
+        "init" => {
+            match args.get(2) {
+                Some(initializer) if !initializer.starts_with('-') => create::run(&args[2..]),
+                _ => init::run(has_flag(&args, &["--yes", "-y"])),
+            }
         },
         "create" => {
             if args.len() < 3 {
                 logger::error("No template name provided for create");
                 return;
             }
-            let create_args = args[2..].join(" ");
-            task::run_create(&create_args);
+            create::run(&args[2..]);
+        },
+        "pack" => publish::pack(has_flag(&args, &["--dry-run"])),
+        "publish" => {
+            let tag = flag_value(&args, "--tag").unwrap_or("latest");
+            publish::publish(tag, flag_value(&args, "--access"), has_flag(&args, &["--dry-run"]));
+        },
+        "cache" => match args.get(2).map(|s| s.as_str()) {
+            Some("ls") => cache::ls(&args[3..]),
+            Some("verify") => cache::verify(),
+            Some("clean") => cache::clean(&args[3..]),
+            Some("add") => cache::add(&args[3..]),
+            Some("dir") => cache::dir(),
+            Some("prune") => cache::prune(flag_value(&args, "--max-size"), flag_value(&args, "--max-unused-days")),
+            _ => {
+                logger::error("Usage: snpm cache <ls|verify|clean|add|dir|prune>");
+                std::process::exit(1);
+            }
         },
         "help" | "h" | "?" | "version" | "v" => {
             info::version();
@@ -51,3 +108,32 @@ fn main() {
     }
 }
 
+
+fn has_flag(args: &[String], names: &[&str]) -> bool {
+    args.iter().any(|arg| names.contains(&arg.as_str()))
+}
+
+fn flag_values(args: &[String], name: &str) -> Vec<String> {
+    let prefix = format!("{}=", name);
+    args.iter()
+        .enumerate()
+        .filter_map(|(i, arg)| {
+            if arg == name {
+                args.get(i + 1).cloned()
+            } else {
+                arg.strip_prefix(&prefix).map(String::from)
+            }
+        })
+        .collect()
+}
+
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let prefix = format!("{}=", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).map(|v| v.as_str())
+        } else {
+            arg.strip_prefix(&prefix)
+        }
+    })
+}
@@ -0,0 +1,247 @@
+use std::fs::{self, File};
+use std::path::Path;
+use crate::{logger, io::net};
+use crate::io::net::Client;
+use crate::io::cache::{self, CacheMode};
+use crate::io::gc;
+use crate::io::logger::colorize;
+use crate::runtime::resolver;
+use crate::utils::config::Config;
+use crate::utils::{range, utils};
+
+/// `snpm cache dir`: prints where the cache lives.
+pub fn dir() {
+    println!("{}", utils::get_cache_directory().display());
+}
+
+/// `snpm cache ls [<name>...]`: lists cached packages, optionally only
+/// those with the given names.
+pub fn ls(names: &[String]) {
+    let mut count = 0;
+    for (path, entry) in cache::package_entries() {
+        let dir_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let listed = names.is_empty()
+            || names.iter().any(|name| dir_name.starts_with(&format!("{}@", cache::package_key(name))));
+        if !listed {
+            continue;
+        }
+
+        count += 1;
+        match entry {
+            Some(entry) => println!("{}@{}", colorize("green", &entry.name), entry.version),
+            None => println!("{} {}", dir_name, colorize("yellow", "(incomplete)")),
+        }
+    }
+
+    println!();
+    logger::info(&format!(
+        "{} package(s) and {} packument(s) in {}",
+        count,
+        cache::metadata_entries().len(),
+        utils::get_cache_directory().display()
+    ));
+}
+
+/// `snpm cache verify`: re-hashes every cached tarball against its recorded
+/// integrity and removes entries that are corrupt or incomplete, along
+/// with packuments that can't be read.
+pub fn verify() {
+    let mut verified = 0;
+    let mut removed = 0;
+
+    for (path, _) in cache::package_entries() {
+        let Some(lock) = lock(&path) else {
+            continue;
+        };
+        match cache::verify_package(&path) {
+            Ok(_) => verified += 1,
+            Err(e) => {
+                logger::warn(&format!("Removing {}: {}", path.display(), e));
+                if remove(&path, &mut removed) {
+                    cache::remove_lock(&path, lock);
+                }
+            }
+        }
+    }
+    for (path, name) in cache::metadata_entries() {
+        if name.is_none() {
+            logger::warn(&format!("Removing {}: unreadable packument", path.display()));
+            remove(&path, &mut removed);
+        }
+    }
+
+    logger::info(&format!(
+        "Verified {} package(s), removed {} corrupt entr{}",
+        verified,
+        removed,
+        if removed == 1 { "y" } else { "ies" }
+    ));
+}
+
+/// `snpm cache clean [<name>[@<range>]...]`: removes everything snpm keeps
+/// in the cache, or only the given packages. Without a range every version and the packument go;
+/// with one only the matching versions do.
+pub fn clean(specs: &[String]) {
+    if specs.is_empty() {
+        let cache_dir = utils::get_cache_directory();
+        let mut removed = 0;
+        for name in cache::CACHE_CONTENTS {
+            let path = cache_dir.join(name);
+            if path.symlink_metadata().is_ok() {
+                remove(&path, &mut removed);
+            }
+        }
+        if removed == 0 {
+            logger::info("The cache is already empty");
+        } else {
+            logger::info(&format!("Emptied the cache at {}", cache_dir.display()));
+        }
+        return;
+    }
+
+    let mut removed = 0;
+    for spec in specs {
+        let (name, range) = resolver::split_spec(spec);
+        let all_versions = !spec[usize::from(spec.starts_with('@'))..].contains('@');
+        let prefix = format!("{}@", cache::package_key(&name));
+
+        for (path, entry) in cache::package_entries() {
+            let dir_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let matches = match &entry {
+                Some(entry) => entry.name == name && (all_versions || range::satisfies(&entry.version, &range)),
+                None => all_versions && dir_name.starts_with(&prefix),
+            };
+            if matches {
+                if let Some(lock) = lock(&path) {
+                    if remove(&path, &mut removed) {
+                        cache::remove_lock(&path, lock);
+                    }
+                }
+            }
+        }
+
+        if all_versions {
+            for (path, cached_name) in cache::metadata_entries() {
+                if cached_name.as_deref() == Some(name.as_str()) {
+                    remove(&path, &mut removed);
+                }
+            }
+        }
+    }
+
+    logger::info(&format!("Removed {} cache entr{}", removed, if removed == 1 { "y" } else { "ies" }));
+}
+
+/// `snpm cache add <spec>...`: downloads packages into the cache without
+/// installing them, for example to warm a Docker layer.
+pub fn add(specs: &[String]) {
+    if specs.is_empty() {
+        logger::error("No package provided for cache add");
+        std::process::exit(1);
+    }
+
+    let client = net::client();
+    let mode = CacheMode::from_config(&Config::load());
+    let mut failed = false;
+
+    for spec in specs {
+        if let Err(e) = add_one(&client, spec, mode) {
+            logger::error(&format!("Failed to add {}: {}", spec, e));
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// `snpm cache prune`: removes packages that haven't been used for a while,
+/// then the least recently used ones until the cache fits its size cap.
+/// `max_size` and `max_unused_days` override the settings.
+pub fn prune(max_size: Option<&str>, max_unused_days: Option<&str>) {
+    let mut limits = gc::Limits::from_config(&Config::load());
+    if let Some(max_size) = max_size {
+        match gc::parse_size(max_size) {
+            Some(size) => limits.max_size = size,
+            None => {
+                logger::error(&format!("Invalid size '{}'", max_size));
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(days) = max_unused_days {
+        match days.parse() {
+            Ok(days) => limits.max_unused_days = days,
+            Err(_) => {
+                logger::error(&format!("Invalid number of days '{}'", days));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let pruned = gc::prune(limits);
+    logger::info(&format!(
+        "Removed {} cache entr{}, freeing {}. {} remain, using {}.",
+        pruned.removed,
+        if pruned.removed == 1 { "y" } else { "ies" },
+        gc::format_size(pruned.freed),
+        pruned.remaining,
+        gc::format_size(pruned.size)
+    ));
+}
+
+fn add_one(client: &Client, spec: &str, mode: CacheMode) -> Result<(), Box<dyn std::error::Error>> {
+    let (name, range) = resolver::split_spec(spec);
+    let metadata = net::fetch_metadata(client, &name, mode)?;
+    let version = range::select_version(&metadata, &range)
+        .ok_or_else(|| format!("No version of '{}' matches {}", name, range))?;
+    let dist = &metadata["versions"][&version]["dist"];
+    let tarball = dist["tarball"]
+        .as_str()
+        .ok_or_else(|| format!("No tarball URL for {}@{}", name, version))?;
+    let integrity = cache::dist_integrity(dist);
+
+    if cache::cached_package(client.cache_dir(), &name, &version, integrity.as_deref()).is_some() {
+        logger::info(&format!("{}@{} is already cached", name, version));
+        return Ok(());
+    }
+    if mode == CacheMode::Offline {
+        return Err(CacheMode::offline_error(&format!("{}@{}", name, version)).into());
+    }
+
+    cache::add_package(client, &name, &version, tarball, integrity.as_deref())?;
+    gc::record_packages(&[(name.clone(), version.clone())]);
+    logger::info(&format!("Added {}@{}", name, version));
+    Ok(())
+}
+
+/// Locks a package entry before checking or removing it, so that no other
+/// snpm process is writing or copying it meanwhile.
+fn lock(path: &Path) -> Option<File> {
+    match cache::lock_entry(path, true) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            logger::warn(&format!("Skipping {}: {}", path.display(), e));
+            None
+        }
+    }
+}
+
+fn remove(path: &Path, removed: &mut usize) -> bool {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Ok(()) => {
+            *removed += 1;
+            true
+        }
+        Err(e) => {
+            logger::warn(&format!("Failed to remove {}: {}", path.display(), e));
+            false
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+use crate::logger;
+use crate::runtime::{npx, resolver};
+use crate::utils::utils;
+
+/// `snpm create <initializer> [args...]`: runs the matching `create-*`
+/// package, then installs dependencies in the project it generated.
+pub fn run(args: &[String]) {
+    let Some((initializer, rest)) = args.split_first() else {
+        logger::error("No template name provided for create");
+        return;
+    };
+
+    let package = initializer_package(initializer);
+
+    println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
+    println!("  > snpm create {}", args.join(" "));
+    println!("  > {}\n", package);
+
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let existing_dirs = subdirectories(&current_dir);
+    let had_package_json = current_dir.join("package.json").exists();
+
+    let code = npx::run_package(&package, rest);
+    if code != 0 {
+        logger::error(&format!("{} failed with exit code: {}", package, code));
+        std::process::exit(code);
+    }
+
+    let Some(project_dir) = generated_project(&current_dir, rest, &existing_dirs, had_package_json) else {
+        return;
+    };
+
+    let manifest = utils::read_json(&project_dir.join("package.json")).unwrap_or_default();
+    if manifest.get("dependencies").is_none() && manifest.get("devDependencies").is_none() {
+        return;
+    }
+
+    println!();
+    logger::info(&format!("Installing dependencies in {}", project_dir.display()));
+
+    let snpm = env::current_exe().unwrap_or_else(|_| PathBuf::from("snpm"));
+    match Command::new(snpm).arg("install").current_dir(&project_dir).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => logger::error(&format!(
+            "snpm install failed with exit code: {}",
+            status.code().unwrap_or(-1)
+        )),
+        Err(e) => logger::error(&format!("Failed to run snpm install: {}", e)),
+    }
+}
+
+/// Maps an initializer to its package the way `npm init` does: `foo` is
+/// `create-foo`, `@scope` is `@scope/create` and `@scope/foo` is
+/// `@scope/create-foo`. A version suffix is carried over.
+pub fn initializer_package(initializer: &str) -> String {
+    let has_version = initializer[usize::from(initializer.starts_with('@'))..].contains('@');
+    let (name, version) = resolver::split_spec(initializer);
+
+    let package = match name.strip_prefix('@') {
+        Some(scoped) => match scoped.split_once('/') {
+            Some((scope, rest)) => format!("@{}/create-{}", scope, rest),
+            None => format!("@{}/create", scoped),
+        },
+        None => format!("create-{}", name),
+    };
+
+    if has_version {
+        format!("{}@{}", package, version)
+    } else {
+        package
+    }
+}
+
+/// Works out where the initializer wrote its project: the directory named
+/// on the command line, a single new directory, or the current directory.
+fn generated_project(
+    current_dir: &Path,
+    args: &[String],
+    existing_dirs: &HashSet<PathBuf>,
+    had_package_json: bool,
+) -> Option<PathBuf> {
+    if let Some(named) = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| current_dir.join(arg))
+        .find(|dir| dir.join("package.json").is_file())
+    {
+        return Some(named);
+    }
+
+    let created: Vec<PathBuf> = subdirectories(current_dir)
+        .into_iter()
+        .filter(|dir| !existing_dirs.contains(dir) && dir.join("package.json").is_file())
+        .collect();
+    if created.len() == 1 {
+        return created.into_iter().next();
+    }
+
+    if !had_package_json && current_dir.join("package.json").is_file() {
+        return Some(current_dir.to_path_buf());
+    }
+
+    None
+}
+
+fn subdirectories(dir: &Path) -> HashSet<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,86 @@
+use std::process::Command;
+use semver::Version;
+use serde_json::Value;
+use crate::logger;
+use crate::runtime::resolver::Tree;
+use crate::utils::range::Range;
+
+/// The version of the `node` on PATH, without the leading `v`.
+pub fn node_version() -> Option<String> {
+    let output = Command::new("node").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = String::from_utf8_lossy(&output.stdout).trim().trim_start_matches('v').to_string();
+    Version::parse(&version).is_ok().then_some(version)
+}
+
+/// Checks `engines.node` of the project and every package in `tree` against
+/// the local node. Problems are warnings, or errors with `engine_strict`, in
+/// which case this returns false.
+pub fn check(tree: &Tree, project: &Value, engine_strict: bool) -> bool {
+    let Some(current) = node_version() else {
+        logger::warn("Could not run `node --version`; skipping engines checks");
+        return true;
+    };
+
+    let mut problems = vec![];
+    if let Some(wanted) = unsupported(project, &current) {
+        let name = project["name"].as_str().unwrap_or("the root project");
+        problems.push((name.to_string(), wanted));
+    }
+    for node in tree.nodes.iter().skip(1) {
+        if let Some(wanted) = unsupported(&node.manifest, &current) {
+            problems.push((format!("{}@{}", node.name, node.version), wanted));
+        }
+    }
+
+    for (package, wanted) in &problems {
+        let message = format!(
+            "Unsupported engine for {}: wanted node {} (current: {})",
+            package, wanted, current
+        );
+        if engine_strict {
+            logger::error(&message);
+        } else {
+            logger::warn(&message);
+        }
+    }
+
+    !engine_strict || problems.is_empty()
+}
+
+/// Warns when the `packageManager` field pins a different package manager,
+/// or a different version of snpm.
+pub fn check_package_manager(project: &Value) {
+    let Some(pinned) = project.get("packageManager").and_then(|p| p.as_str()) else {
+        return;
+    };
+
+    let (name, version) = match pinned.rsplit_once('@') {
+        Some((name, version)) if !name.is_empty() => (name, version.split('+').next().unwrap_or(version)),
+        _ => (pinned, ""),
+    };
+
+    if name != "snpm" {
+        logger::warn(&format!(
+            "This project declares \"packageManager\": \"{}\". Installing with snpm may produce a different node_modules layout; consider using {}.",
+            pinned, name
+        ));
+    } else if !version.is_empty() && version != env!("CARGO_PKG_VERSION") {
+        logger::warn(&format!(
+            "This project expects snpm {}, but this is snpm {}.",
+            version,
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+}
+
+/// The `engines.node` range of a manifest if `current` doesn't satisfy it.
+/// Ranges that can't be parsed are not reported.
+fn unsupported(manifest: &Value, current: &str) -> Option<String> {
+    let wanted = manifest.get("engines")?.get("node")?.as_str()?;
+    let range = Range::parse(wanted).ok()?;
+    let version = Version::parse(current).ok()?;
+    (!range.matches(&version)).then(|| wanted.to_string())
+}
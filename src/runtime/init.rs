@@ -0,0 +1,214 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::{env, fs};
+use serde_json::{Map, Value};
+use crate::logger;
+use crate::io::logger::colorize;
+use crate::utils::config::Config;
+use crate::utils::utils;
+
+const DEFAULT_TEST_SCRIPT: &str = "echo \"Error: no test specified\" && exit 1";
+
+/// `snpm init [-y]`: writes a package.json for the current directory,
+/// asking for each field unless `yes` is set. Fields of an existing
+/// package.json are kept and offered as defaults.
+pub fn run(yes: bool) {
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let package_file = current_dir.join("package.json");
+    let config = Config::load();
+
+    let mut json = if package_file.exists() {
+        match utils::read_json(&package_file) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => Map::new(),
+            Err(e) => {
+                logger::error(&e.to_string());
+                std::process::exit(1);
+            }
+        }
+    } else {
+        Map::new()
+    };
+
+    let existing = |json: &Map<String, Value>, key: &str| json.get(key).and_then(|v| v.as_str()).map(String::from);
+
+    let default_name = existing(&json, "name").unwrap_or_else(|| default_name(&current_dir));
+    let default_version = existing(&json, "version")
+        .or_else(|| config.get("init-version").map(String::from))
+        .unwrap_or_else(|| "1.0.0".to_string());
+    let default_description = existing(&json, "description").unwrap_or_default();
+    let default_main = existing(&json, "main").unwrap_or_else(|| "index.js".to_string());
+    let default_test = json
+        .get("scripts")
+        .and_then(|s| s.get("test"))
+        .and_then(|t| t.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| DEFAULT_TEST_SCRIPT.to_string());
+    let default_author = existing(&json, "author")
+        .or_else(|| config.get("init-author-name").map(String::from))
+        .unwrap_or_default();
+    let default_license = existing(&json, "license")
+        .or_else(|| config.get("init-license").map(String::from))
+        .unwrap_or_else(|| "ISC".to_string());
+    let default_type = existing(&json, "type")
+        .or_else(|| config.get("init-type").map(String::from))
+        .unwrap_or_else(|| "commonjs".to_string());
+
+    if !yes {
+        println!("This utility will walk you through creating a package.json file.");
+        println!("Press ^C at any time to quit.\n");
+    }
+
+    let name = loop {
+        let name = ask(yes, "package name", &default_name);
+        match validate_name(&name) {
+            Ok(()) => break name,
+            Err(e) if yes => {
+                logger::error(&format!("Invalid package name '{}': {}", name, e));
+                std::process::exit(1);
+            }
+            Err(e) => logger::warn(&format!("Invalid package name: {}", e)),
+        }
+    };
+    let version = ask(yes, "version", &default_version);
+    let description = ask(yes, "description", &default_description);
+    let main = ask(yes, "entry point", &default_main);
+    let test = ask(yes, "test command", &default_test);
+    let author = ask(yes, "author", &default_author);
+    let license = ask(yes, "license", &default_license);
+    let module_type = ask(yes, "type", &default_type);
+
+    json.insert("name".to_string(), Value::String(name));
+    json.insert("version".to_string(), Value::String(version));
+    json.insert("description".to_string(), Value::String(description));
+    json.insert("main".to_string(), Value::String(main));
+
+    let scripts = json
+        .entry("scripts")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Some(scripts) = scripts.as_object_mut() {
+        scripts.insert("test".to_string(), Value::String(test));
+    }
+
+    json.insert("author".to_string(), Value::String(author));
+    json.insert("license".to_string(), Value::String(license));
+    json.insert("type".to_string(), Value::String(module_type));
+
+    let content = match serde_json::to_string_pretty(&Value::Object(json)) {
+        Ok(content) => content,
+        Err(e) => {
+            logger::error(&format!("Failed to serialize package.json: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    if !yes {
+        println!("\nAbout to write to {}:\n\n{}\n", package_file.display(), content);
+        let answer = ask(false, "Is this OK?", "yes");
+        if !answer.to_lowercase().starts_with('y') {
+            logger::info("Aborted.");
+            return;
+        }
+    }
+
+    if let Err(e) = fs::write(&package_file, format!("{}\n", content)) {
+        logger::error(&format!("Failed to write package.json: {}", e));
+        std::process::exit(1);
+    }
+
+    if yes {
+        println!("Wrote to {}:\n\n{}\n", package_file.display(), content);
+    }
+    logger::info(&format!("Created {}", colorize("green", "package.json")));
+}
+
+fn ask(yes: bool, prompt: &str, default: &str) -> String {
+    if yes {
+        return default.to_string();
+    }
+
+    if default.is_empty() {
+        print!("{}: ", prompt);
+    } else {
+        print!("{}: ({}) ", prompt, default);
+    }
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).unwrap_or(0) == 0 {
+        println!();
+        return default.to_string();
+    }
+
+    match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+/// The directory name turned into a valid package name.
+fn default_name(dir: &Path) -> String {
+    let raw = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("package");
+
+    let name: String = raw
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-._~".contains(c) { c } else { '-' })
+        .collect();
+    let name = name.trim_start_matches(['.', '_']).to_string();
+
+    if name.is_empty() {
+        "package".to_string()
+    } else {
+        name
+    }
+}
+
+/// Checks npm's rules for new package names.
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("name cannot be empty".to_string());
+    }
+    if name.len() > 214 {
+        return Err("name can no longer contain more than 214 characters".to_string());
+    }
+    if name.starts_with('.') || name.starts_with('_') {
+        return Err("name cannot start with a period or underscore".to_string());
+    }
+    if name != name.to_lowercase() {
+        return Err("name can no longer contain capital letters".to_string());
+    }
+    if name.trim() != name {
+        return Err("name cannot contain leading or trailing spaces".to_string());
+    }
+
+    let unscoped = match name.strip_prefix('@') {
+        Some(scoped) => match scoped.split_once('/') {
+            Some((scope, rest)) if !scope.is_empty() && !rest.is_empty() => {
+                if !is_url_safe(scope) {
+                    return Err("scope can only contain URL-friendly characters".to_string());
+                }
+                rest
+            }
+            _ => return Err("scoped names must look like @scope/name".to_string()),
+        },
+        None => name,
+    };
+
+    if !is_url_safe(unscoped) {
+        return Err("name can only contain URL-friendly characters".to_string());
+    }
+    if unscoped == "node_modules" || unscoped == "favicon.ico" {
+        return Err(format!("{} is not a valid package name", unscoped));
+    }
+
+    Ok(())
+}
+
+fn is_url_safe(part: &str) -> bool {
+    part.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+}
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use serde_json::Value;
+use crate::{logger, utils::utils};
+use crate::io::net::Client;
+use crate::io::cache::{self, CacheMode};
+use crate::io::gc;
+use crate::runtime::resolver::{self, Tree};
+use crate::utils::lockfile::LockFile;
+
+/// A package to download into the cache and copy to `destination`.
+#[derive(Clone)]
+struct PendingPackage {
+    name: String,
+    version: String,
+    tarball: String,
+    integrity: Option<String>,
+    destination: PathBuf,
+    optional: bool,
+}
+
+/// Writes every package of `tree` into `root`, one depth at a time so that
+/// parents exist before their nested `node_modules`, then links bins.
+/// Packages are copied from the snpm cache, downloading only those it
+/// doesn't hold yet; those whose installed version already matches are
+/// left untouched.
+/// Workspace members and `link:` dependencies are symlinked, and git,
+/// tarball and `file:` dependencies are copied from their fetched source.
+/// Optional packages that fail to install are removed together with
+/// everything nested below them. With `--offline`, packages missing from
+/// the cache are an error.
+pub fn install_tree(client: &Client, tree: &Tree, root: &Path, mode: CacheMode) -> Result<(), Box<dyn std::error::Error>> {
+    let max_depth = (1..tree.nodes.len()).map(|i| tree.depth(i)).max().unwrap_or(0);
+    let mut failed: Vec<PathBuf> = vec![];
+
+    for depth in 1..=max_depth {
+        let mut pending: BTreeMap<PathBuf, Vec<PendingPackage>> = BTreeMap::new();
+
+        for index in (1..tree.nodes.len()).filter(|&i| tree.depth(i) == depth) {
+            let node = &tree.nodes[index];
+            let destination = root.join(tree.location(index));
+            if failed.iter().any(|path| destination.starts_with(path)) {
+                continue;
+            }
+
+            if let Some(link) = &node.link {
+                link_directory(&destination, &root.join(link))?;
+                continue;
+            }
+            if let Some(source) = &node.source {
+                logger::info(&format!("Installing {}@{} from {}", node.name, node.version, node.spec));
+                match utils::copy_dir_contents(source, &destination) {
+                    Ok(()) => {}
+                    Err(_) if node.optional => {
+                        let _ = fs::remove_dir_all(&destination);
+                        failed.push(destination);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+            if resolver::installed_version(&destination).as_deref() == Some(node.version.as_str()) {
+                continue;
+            }
+            let Some(tarball) = node.manifest["dist"]["tarball"].as_str() else {
+                return Err(format!("No tarball URL for {}@{}", node.name, node.version).into());
+            };
+
+            let cache_path = cache::package_dir(client.cache_dir(), &node.name, &node.version);
+            pending.entry(cache_path).or_default().push(PendingPackage {
+                name: node.name.clone(),
+                version: node.version.clone(),
+                tarball: tarball.to_string(),
+                integrity: cache::dist_integrity(&node.manifest["dist"]),
+                destination,
+                optional: node.optional,
+            });
+        }
+
+        if pending.is_empty() {
+            continue;
+        }
+
+        // Packages sharing a cache entry stay in one group so that the entry
+        // is only written by one thread. There are as many threads as
+        // requests may run at once, since most of the time goes to downloads.
+        let groups: Vec<(PathBuf, Vec<PendingPackage>)> = pending.into_iter().collect();
+        let chunk_size = groups.len().div_ceil(client.concurrency());
+        let handles: Vec<_> = groups
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+                let client = client.clone();
+                thread::spawn(move || -> Result<Vec<PathBuf>, String> {
+                    let mut failed = vec![];
+                    for (_, packages) in chunk {
+                        for package in packages {
+                            logger::info(&format!("Installing {}@{}", package.name, package.version));
+                            match install_from_cache(&client, &package, mode) {
+                                Ok(()) => {}
+                                Err(_) if package.optional => {
+                                    let _ = fs::remove_dir_all(&package.destination);
+                                    failed.push(package.destination);
+                                }
+                                Err(e) => return Err(e),
+                            }
+                        }
+                    }
+                    Ok(failed)
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            failed.extend(handle.join().map_err(|_| "An install thread panicked")??);
+        }
+    }
+
+    let used: Vec<(String, String)> = tree.nodes[1..]
+        .iter()
+        .filter(|node| node.link.is_none() && node.source.is_none())
+        .map(|node| (node.name.clone(), node.version.clone()))
+        .collect();
+    gc::record_packages(&used);
+
+    link_bins(tree, root)
+}
+
+/// Copies a package from the cache into place, downloading it first unless
+/// the cache already holds a valid copy. The entry is locked while copying
+/// so that another snpm process can't replace or prune it meanwhile.
+fn install_from_cache(client: &Client, package: &PendingPackage, mode: CacheMode) -> Result<(), String> {
+    let integrity = package.integrity.as_deref();
+    let dir = cache::package_dir(client.cache_dir(), &package.name, &package.version);
+    let copy_cached = || -> Result<bool, String> {
+        let _lock = cache::lock_entry(&dir, false)
+            .map_err(|e| format!("Failed to lock the cache entry of {}@{}: {}", package.name, package.version, e))?;
+        match cache::cached_package(client.cache_dir(), &package.name, &package.version, integrity) {
+            Some(contents) => utils::copy_dir_contents(&contents, &package.destination)
+                .map(|()| true)
+                .map_err(|e| format!("Failed to copy {}@{}: {}", package.name, package.version, e)),
+            None => Ok(false),
+        }
+    };
+
+    if copy_cached()? {
+        return Ok(());
+    }
+    if mode == CacheMode::Offline {
+        return Err(CacheMode::offline_error(&format!("{}@{}", package.name, package.version)));
+    }
+    cache::add_package(client, &package.name, &package.version, &package.tarball, integrity)
+        .map_err(|e| format!("Failed to download {}@{}: {}", package.name, package.version, e))?;
+
+    if copy_cached()? {
+        Ok(())
+    } else {
+        Err(format!("{}@{} was removed from the cache while installing", package.name, package.version))
+    }
+}
+
+/// Removes packages recorded in the previous lockfile whose location is no
+/// longer part of `tree`.
+pub fn prune(root: &Path, previous: &LockFile, tree: &Tree) {
+    let current: HashSet<String> = (1..tree.nodes.len()).map(|i| tree.location_key(i)).collect();
+
+    for (location, entry) in previous.entries() {
+        if current.contains(location) || entry.use_npm_fallback {
+            continue;
+        }
+
+        let path = root.join(location);
+        let removed = if path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+            remove_link(&path)
+        } else if path.exists() {
+            fs::remove_dir_all(&path)
+        } else {
+            continue;
+        };
+
+        match removed {
+            Ok(()) => logger::info(&format!("Removed {}", location)),
+            Err(e) => logger::warn(&format!("Failed to remove {}: {}", location, e)),
+        }
+
+        if let Some(scope_dir) = path.parent().filter(|p| {
+            p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('@'))
+        }) {
+            let _ = fs::remove_dir(scope_dir);
+        }
+    }
+}
+
+fn link_directory(destination: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let parent = destination.parent().unwrap_or(Path::new("."));
+    let relative = utils::relative_path(parent, target);
+
+    if let Ok(metadata) = destination.symlink_metadata() {
+        if !metadata.file_type().is_symlink() {
+            fs::remove_dir_all(destination)?;
+        } else if fs::read_link(destination).is_ok_and(|existing| existing == relative || existing == target) {
+            return Ok(());
+        } else {
+            remove_link(destination)?;
+        }
+    }
+
+    fs::create_dir_all(parent)?;
+    logger::info(&format!("Linking {}", target.display()));
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(relative, destination)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(target, destination)?;
+
+    Ok(())
+}
+
+fn remove_link(path: &Path) -> std::io::Result<()> {
+    #[cfg(windows)]
+    return fs::remove_dir(path);
+    #[cfg(not(windows))]
+    fs::remove_file(path)
+}
+
+/// Links each package's bins into the `.bin` directory of the
+/// `node_modules` it was installed into. Bins are read from the installed
+/// package.json, falling back to the registry manifest.
+pub fn link_bins(tree: &Tree, root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    for (index, node) in tree.nodes.iter().enumerate().skip(1) {
+        let package_dir = root.join(tree.location(index));
+        if !package_dir.exists() {
+            continue;
+        }
+        let manifest = utils::read_json(&package_dir.join("package.json"))
+            .unwrap_or_else(|_| node.manifest.clone());
+        let bins = bin_entries(&manifest, &node.name);
+        if bins.is_empty() {
+            continue;
+        }
+
+        let parent = node.parent.unwrap_or(0);
+        let bin_dir = root.join(tree.location(parent)).join("node_modules").join(".bin");
+        fs::create_dir_all(&bin_dir)?;
+
+        for (bin_name, relative) in bins {
+            link_bin(&bin_dir, &bin_name, &package_dir, &relative)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Normalizes a manifest's `bin` field, which is either a path named after
+/// the package or a map of command names to paths. Entries that would escape
+/// the package directory are dropped.
+pub fn bin_entries(manifest: &Value, package_name: &str) -> Vec<(String, String)> {
+    let entries: Vec<(String, String)> = match manifest.get("bin") {
+        Some(Value::String(path)) => {
+            let name = package_name.rsplit('/').next().unwrap_or(package_name);
+            vec![(name.to_string(), path.clone())]
+        }
+        Some(Value::Object(map)) => map
+            .iter()
+            .filter_map(|(name, path)| path.as_str().map(|p| (name.clone(), p.to_string())))
+            .collect(),
+        _ => vec![],
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, path)| (name.rsplit('/').next().unwrap_or(&name).to_string(), path))
+        .filter(|(name, path)| {
+            !name.is_empty()
+                && name != ".."
+                && Path::new(path)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn link_bin(bin_dir: &Path, bin_name: &str, package_dir: &Path, relative: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let target = package_dir.join(relative);
+    if !target.exists() {
+        logger::warn(&format!("Bin '{}' points to missing file {}", bin_name, target.display()));
+        return Ok(());
+    }
+
+    let mut permissions = fs::metadata(&target)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    fs::set_permissions(&target, permissions)?;
+
+    let link = bin_dir.join(bin_name);
+    if link.symlink_metadata().is_ok() {
+        fs::remove_file(&link)?;
+    }
+
+    let link_target = match target.strip_prefix(bin_dir.parent().unwrap_or(bin_dir)) {
+        Ok(inside) => Path::new("..").join(inside),
+        Err(_) => target.clone(),
+    };
+    std::os::unix::fs::symlink(link_target, link)?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn link_bin(bin_dir: &Path, bin_name: &str, package_dir: &Path, relative: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let target = package_dir.join(relative);
+    if !target.exists() {
+        logger::warn(&format!("Bin '{}' points to missing file {}", bin_name, target.display()));
+        return Ok(());
+    }
+
+    let shim = format!("@node \"{}\" %*\r\n", target.display());
+    fs::write(bin_dir.join(format!("{}.cmd", bin_name)), shim)?;
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::utils::config::Config;
+
+/// Builds the environment npm exposes to package scripts and lifecycle hooks.
+pub fn environment(package_dir: &Path, json: &Value, event: &str, script: &str) -> Vec<(String, String)> {
+    let mut vars = vec![
+        (
+            "npm_package_json".to_string(),
+            package_dir.join("package.json").display().to_string(),
+        ),
+        ("npm_lifecycle_event".to_string(), event.to_string()),
+        ("npm_lifecycle_script".to_string(), script.to_string()),
+        ("npm_command".to_string(), "run-script".to_string()),
+        ("npm_config_user_agent".to_string(), user_agent()),
+    ];
+
+    for field in ["name", "version", "config", "engines", "bin"] {
+        if let Some(value) = json.get(field) {
+            flatten(&format!("npm_package_{}", field), value, &mut vars);
+        }
+    }
+
+    for (key, value) in Config::load().exported() {
+        vars.push((format!("npm_config_{}", sanitize(key)), value.clone()));
+    }
+
+    if let Ok(exec_path) = env::current_exe() {
+        vars.push(("npm_execpath".to_string(), exec_path.display().to_string()));
+    }
+
+    if let Ok(node_path) = which::which("node") {
+        vars.push(("npm_node_execpath".to_string(), node_path.display().to_string()));
+        vars.push(("NODE".to_string(), node_path.display().to_string()));
+    }
+
+    let init_cwd = env::var("INIT_CWD").ok().or_else(|| {
+        env::current_dir()
+            .ok()
+            .map(|dir| dir.display().to_string())
+    });
+    if let Some(init_cwd) = init_cwd {
+        vars.push(("INIT_CWD".to_string(), init_cwd));
+    }
+
+    if let Some(path) = script_path(package_dir) {
+        vars.push(("PATH".to_string(), path));
+    }
+
+    vars
+}
+
+fn flatten(prefix: &str, value: &Value, vars: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, nested) in map {
+                flatten(&format!("{}_{}", prefix, sanitize(key)), nested, vars);
+            }
+        }
+        Value::String(s) => vars.push((prefix.to_string(), s.clone())),
+        Value::Null => {}
+        other => vars.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+fn sanitize(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn user_agent() -> String {
+    format!(
+        "snpm/{} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH
+    )
+}
+
+/// Prepends every `node_modules/.bin` from the package up to the filesystem
+/// root to `PATH`, nearest first.
+fn script_path(package_dir: &Path) -> Option<String> {
+    let mut paths: Vec<PathBuf> = package_dir
+        .ancestors()
+        .map(|dir| dir.join("node_modules").join(".bin"))
+        .collect();
+
+    if let Some(existing) = env::var_os("PATH") {
+        paths.extend(env::split_paths(&existing));
+    }
+
+    env::join_paths(paths)
+        .ok()
+        .and_then(|joined| joined.into_string().ok())
+}
@@ -1,2 +1,13 @@
 pub mod task;
 pub mod package;
+pub mod lifecycle;
+pub mod runner;
+pub mod resolver;
+pub mod sources;
+pub mod installer;
+pub mod npx;
+pub mod create;
+pub mod init;
+pub mod publish;
+pub mod engines;
+pub mod cache;
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+use serde_json::Value;
+use crate::{logger, io::net};
+use crate::io::cache::{self, CacheMode};
+use crate::io::gc;
+use crate::runtime::{installer, lifecycle};
+use crate::runtime::resolver::{self, Resolver};
+use crate::utils::config::Config;
+use crate::utils::{range, utils};
+
+/// `snpm x [-p <spec>]... <command-or-spec> [args...]`.
+///
+/// Without `--package`, the first argument names both the package and its
+/// command. A matching bin in a local `node_modules/.bin` is preferred;
+/// otherwise the package is installed into a prefix in the snpm cache.
+pub fn run(args: &[String]) {
+    let mut packages: Vec<String> = vec![];
+    let mut rest = args;
+
+    while let Some((first, remaining)) = rest.split_first() {
+        if first == "--package" || first == "-p" {
+            let Some((spec, remaining)) = remaining.split_first() else {
+                logger::error(&format!("Missing package spec after {}", first));
+                std::process::exit(1);
+            };
+            packages.push(spec.clone());
+            rest = remaining;
+        } else if let Some(spec) = first.strip_prefix("--package=") {
+            packages.push(spec.to_string());
+            rest = remaining;
+        } else if first == "--debug" {
+            rest = remaining;
+        } else {
+            break;
+        }
+    }
+
+    let Some((target, command_args)) = rest.split_first() else {
+        logger::error("No package name provided for execution");
+        std::process::exit(1);
+    };
+
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+
+    if packages.is_empty() {
+        let (name, version_range) = resolver::split_spec(target);
+        let bin_name = name.rsplit('/').next().unwrap_or(&name).to_string();
+
+        if let Some(local) = find_local_bin(&current_dir, &name, &version_range, &bin_name) {
+            std::process::exit(execute(&local, command_args, None));
+        }
+    }
+
+    println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
+    println!("  > snpm x {}\n", args.join(" "));
+
+    if packages.is_empty() {
+        std::process::exit(run_package(target, command_args));
+    }
+
+    let prefix = match ensure_installed(&packages) {
+        Ok(prefix) => prefix,
+        Err(e) => {
+            logger::error(&format!("Failed to install {}: {}", packages.join(", "), e));
+            std::process::exit(1);
+        }
+    };
+
+    let bin_dir = prefix.join("node_modules").join(".bin");
+    std::process::exit(execute(&bin_dir.join(target), command_args, Some(&bin_dir)));
+}
+
+/// Installs the package named by `spec` into the cache prefix and runs its
+/// default command, returning the exit code.
+pub fn run_package(spec: &str, args: &[String]) -> i32 {
+    let (name, _) = resolver::split_spec(spec);
+    let preferred = name.rsplit('/').next().unwrap_or(&name).to_string();
+
+    let prefix = match ensure_installed(&[spec.to_string()]) {
+        Ok(prefix) => prefix,
+        Err(e) => {
+            logger::error(&format!("Failed to install {}: {}", spec, e));
+            return 1;
+        }
+    };
+
+    let Some(bin_name) = choose_bin(&prefix, &name, &preferred) else {
+        logger::error(&format!("Package '{}' does not provide a '{}' command", name, preferred));
+        return 1;
+    };
+
+    let bin_dir = prefix.join("node_modules").join(".bin");
+    execute(&bin_dir.join(bin_name), args, Some(&bin_dir))
+}
+
+/// Installs `specs` and their dependencies into a prefix inside the snpm
+/// cache, reusing it when the resolved versions are already in place. The
+/// prefix is locked meanwhile, so concurrent runs don't install into it at
+/// the same time.
+pub fn ensure_installed(specs: &[String]) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut sorted = specs.to_vec();
+    sorted.sort();
+    let key: String = sorted
+        .join("+")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "@.-_+".contains(c) { c } else { '_' })
+        .collect();
+    let prefix = utils::get_cache_directory().join("_npx").join(key);
+    let _lock = cache::lock_entry(&prefix, true)?;
+    fs::create_dir_all(&prefix)?;
+
+    let dependencies: Vec<(String, String)> = specs.iter().map(|spec| resolver::split_spec(spec)).collect();
+    let manifest = serde_json::json!({
+        "dependencies": dependencies
+            .iter()
+            .map(|(name, spec)| (name.clone(), Value::String(spec.clone())))
+            .collect::<serde_json::Map<String, Value>>()
+    });
+    fs::write(prefix.join("package.json"), serde_json::to_string_pretty(&manifest)?)?;
+
+    let client = net::client();
+    let cache_mode = CacheMode::from_config(&Config::load());
+    let tree = Resolver::new(client.clone()).cache_mode(cache_mode).resolve(&dependencies)?;
+    if let Some((name, spec)) = tree.unresolved.first() {
+        return Err(format!("Could not resolve {}@{}", name, spec).into());
+    }
+    installer::install_tree(&client, &tree, &prefix, cache_mode)?;
+    gc::record_use(&[&prefix]);
+
+    Ok(prefix)
+}
+
+/// Looks for `bin_name` in the `node_modules/.bin` of `dir` or any parent,
+/// as long as the installed package satisfies the requested range.
+fn find_local_bin(dir: &Path, package_name: &str, version_range: &str, bin_name: &str) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        let node_modules = ancestor.join("node_modules");
+        let bin = node_modules.join(".bin").join(bin_name);
+        if !bin.exists() {
+            continue;
+        }
+
+        let installed = resolver::installed_version(&node_modules.join(package_name));
+        let acceptable = version_range == "latest"
+            || installed.is_some_and(|version| range::satisfies(&version, version_range));
+        if acceptable {
+            return Some(bin);
+        }
+    }
+    None
+}
+
+/// Picks the command to run from a package: its only bin, or the one named
+/// after the package.
+fn choose_bin(prefix: &Path, package_name: &str, preferred: &str) -> Option<String> {
+    let package_json = prefix.join("node_modules").join(package_name).join("package.json");
+    let manifest = utils::read_json(&package_json).ok()?;
+    let bins = installer::bin_entries(&manifest, package_name);
+
+    if bins.len() == 1 {
+        return Some(bins[0].0.clone());
+    }
+    bins.into_iter()
+        .map(|(name, _)| name)
+        .find(|name| name == preferred)
+}
+
+fn execute(bin: &Path, args: &[String], extra_path: Option<&Path>) -> i32 {
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let json = utils::read_json(&current_dir.join("package.json")).unwrap_or(Value::Null);
+    let command_line = format!("{} {}", bin.display(), args.join(" "));
+    let mut environment = lifecycle::environment(&current_dir, &json, "npx", command_line.trim());
+
+    if let Some(extra_path) = extra_path {
+        for (key, value) in environment.iter_mut() {
+            if key == "PATH" {
+                let mut paths = vec![extra_path.to_path_buf()];
+                paths.extend(env::split_paths(value.as_str()));
+                if let Some(joined) = env::join_paths(paths).ok().and_then(|p| p.into_string().ok()) {
+                    *value = joined;
+                }
+            }
+        }
+    }
+
+    let program = match (bin.parent(), bin.file_name()) {
+        (Some(dir), Some(name)) => which::which_in(name, Some(dir), &current_dir).unwrap_or_else(|_| bin.to_path_buf()),
+        _ => bin.to_path_buf(),
+    };
+
+    match Command::new(&program)
+        .args(args)
+        .current_dir(&current_dir)
+        .envs(environment)
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            logger::error(&format!("Failed to execute {}: {}", program.display(), e));
+            1
+        }
+    }
+}
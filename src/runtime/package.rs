@@ -1,27 +1,85 @@
-use std::{fs, thread};
+use std::{env, fs};
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc};
-use serde_json::Value;
 use crate::{logger, io::net, utils::utils};
-use crate::utils::lockfile;
+use crate::runtime::{engines, installer};
+use crate::runtime::resolver::{self, Resolver};
+use crate::io::cache::CacheMode;
+use crate::io::gc;
+use crate::utils::config::Config;
+use crate::utils::lockfile::{self, LockFileEntry};
+use crate::utils::overrides::Overrides;
+use crate::utils::platform::Platform;
+use crate::utils::workspace;
 use crate::io::logger::colorize;
 use crate::utils::utils::ASCII_ART;
 
-pub fn install(debug_mode: bool, force_mode: bool) {
-    let package_file = Path::new("package.json");
+#[derive(Default)]
+pub struct InstallOptions {
+    pub debug_mode: bool,
+    pub force_mode: bool,
+    /// Don't install peer dependencies or report problems with them.
+    pub legacy_peer_deps: bool,
+    /// Fail instead of warning when peer dependencies are unmet.
+    pub strict_peer_deps: bool,
+    /// Fail instead of warning when `engines.node` doesn't match.
+    pub engine_strict: bool,
+    /// Install from the cache only, failing on anything missing from it.
+    pub offline: bool,
+    /// Use cached metadata and packages without revalidating them.
+    pub prefer_offline: bool,
+    /// Install for another platform, overriding the `os`, `cpu` and `libc`
+    /// settings.
+    pub os: Option<String>,
+    pub cpu: Option<String>,
+    pub libc: Option<String>,
+}
+
+pub fn install(options: &InstallOptions) {
+    let mut config = Config::load();
+    for (key, value) in [("os", &options.os), ("cpu", &options.cpu), ("libc", &options.libc)] {
+        if let Some(value) = value {
+            config.set(key, value);
+        }
+    }
+    for (key, enabled) in [("offline", options.offline), ("prefer-offline", options.prefer_offline)] {
+        if enabled {
+            config.set(key, "true");
+        }
+    }
+    let cache_mode = CacheMode::from_config(&config);
+    let legacy_peer_deps = options.legacy_peer_deps || config.get_bool("legacy-peer-deps");
+    let strict_peer_deps = options.strict_peer_deps || config.get_bool("strict-peer-deps");
+    let engine_strict = options.engine_strict || config.get_bool("engine-strict");
+
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let root = workspace::find_root(&current_dir).unwrap_or(current_dir);
+    let package_file = root.join("package.json");
 
     if !package_file.exists() {
         logger::error("No package.json file found in the current directory. Please create one.");
         return;
     }
 
-    let content = fs::read_to_string(package_file).expect("Failed to read package.json");
-    let json: Value = serde_json::from_str(&content).expect("Failed to parse package.json");
+    let json = match utils::read_json(&package_file) {
+        Ok(json) => json,
+        Err(e) => {
+            logger::error(&e.to_string());
+            return;
+        }
+    };
 
-    let dependencies = json.get("dependencies").and_then(|d| d.as_object());
-    let dev_dependencies = json.get("devDependencies").and_then(|d| d.as_object());
+    let members = workspace::load_members(&root, &json);
+    let overrides = match Overrides::from_manifest(&json) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            logger::error(&format!("Invalid overrides: {}", e));
+            std::process::exit(1);
+        }
+    };
+    let dependencies = resolver::all_dependencies_of(&json);
+    let optional_dependencies = resolver::optional_dependencies_of(&json);
 
-    if dependencies.is_none() && dev_dependencies.is_none() {
+    if dependencies.is_empty() && optional_dependencies.is_empty() && members.is_empty() {
         logger::error("No dependencies or devDependencies found in package.json");
         return;
     }
@@ -29,13 +87,22 @@ pub fn install(debug_mode: bool, force_mode: bool) {
     println!("{}", colorize("red", ASCII_ART));
     println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
     logger::info("Installing packages...\n");
+    engines::check_package_manager(&json);
 
-    let lockfile = lockfile::LockFile::load().unwrap_or_else(|e| {
+    if !members.is_empty() {
+        logger::info(&format!(
+            "Found {} workspace package(s): {}",
+            members.len(),
+            members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>().join(", ")
+        ));
+    }
+
+    let mut lockfile = lockfile::LockFile::load_from(&root).unwrap_or_else(|e| {
         logger::error(&format!(
             "Failed to load lockfile: {}. Creating new one.",
             e
         ));
-        lockfile::LockFile::new()
+        lockfile::LockFile::new_in(&root)
     });
 
     let cache_dir = utils::get_cache_directory();
@@ -43,157 +110,149 @@ pub fn install(debug_mode: bool, force_mode: bool) {
         fs::create_dir_all(&cache_dir).expect("Failed to create cache directory");
     }
 
-    let (tx, rx): (
-        mpsc::Sender<(String, String, PathBuf, bool)>,
-        mpsc::Receiver<(String, String, PathBuf, bool)>,
-    ) = mpsc::channel();
-    let rx = Arc::new(std::sync::Mutex::new(rx));
-    let lockfile = Arc::new(std::sync::Mutex::new(lockfile));
-
-    let node_modules = PathBuf::from("node_modules");
+    let node_modules = root.join("node_modules");
     if !node_modules.exists() {
         fs::create_dir_all(&node_modules).expect("Failed to create node_modules directory");
     }
 
-    let num_threads = num_cpus::get();
-    let mut handles = vec![];
-
-    for _ in 0..num_threads {
-        let rx = Arc::clone(&rx);
-        let lockfile = Arc::clone(&lockfile);
-        let handle = thread::spawn(move || loop {
-            let package = match rx.lock().unwrap().recv() {
-                Ok((name, version, cache_path, is_dev)) => {
-                    (name, version, cache_path.to_path_buf(), is_dev)
-                }
-                Err(_) => break,
-            };
-
-            let package_type = if package.3 {
-                "devDependency"
-            } else {
-                "dependency"
-            };
-            logger::info(&format!("Installing {} {}", package_type, package.0));
-
-            let mut should_use_npm = lockfile
-                .lock()
-                .unwrap()
-                .should_use_npm(&package.0, &package.1);
-
-            if !should_use_npm {
-                match net::download_and_cache_package(&package.2, debug_mode, force_mode) {
-                    Ok((resolved_version, tarball_url)) => {
-                        lockfile.lock().unwrap().add_package(
-                            package.0.clone(),
-                            package.1.clone(),
-                            Some(tarball_url),
-                            false,
-                            resolved_version,
-                        );
-
-                        let target_dir = PathBuf::from("node_modules").join(&package.0);
-                        if target_dir.exists() {
-                            let _ = fs::remove_dir_all(&target_dir);
-                        }
-
-                        if let Err(e) = fs::create_dir_all(&target_dir) {
-                            logger::error(&format!("Failed to create package directory: {}", e));
-                            continue;
-                        }
-
-                        if let Err(e) = utils::copy_dir_contents(&package.2, &target_dir) {
-                            logger::error(&format!("Failed to copy package contents: {}", e));
-                        }
-                    }
-                    Err(e) => {
-                        should_use_npm = true;
-                        logger::error(&format!(
-                            "Failed to download package: {}. Falling back to npm...",
-                            e
-                        ));
-                    }
-                }
-            }
+    let (npm_packages, registry_packages): (Vec<_>, Vec<_>) = dependencies
+        .into_iter()
+        .partition(|(name, version)| lockfile.should_use_npm(name, version));
 
-            if should_use_npm {
-                lockfile.lock().unwrap().add_package(
-                    package.0.clone(),
-                    package.1.clone(),
-                    None,
-                    true,
-                    package.1.clone(),
-                );
-
-                let package_spec = format!("{}@{}", package.0, package.1);
-                let (shell, shell_arg) = if cfg!(windows) {
-                    ("cmd", "/C")
-                } else {
-                    ("sh", "-c")
-                };
-
-                match std::process::Command::new(shell)
-                    .arg(shell_arg)
-                    .arg(format!(
-                        "npm install {}{}",
-                        if package.3 { "--save-dev " } else { "" },
-                        package_spec
-                    ))
-                    .status()
-                {
-                    Ok(status) => {
-                        if status.success() {
-                            logger::info(&format!(
-                                "Successfully installed {} using npm",
-                                package_spec
-                            ));
-                        } else {
-                            logger::error(&format!(
-                                "npm install failed for {} with exit code: {}",
-                                package_spec,
-                                status.code().unwrap_or(-1)
-                            ));
-                        }
-                    }
-                    Err(e) => logger::error(&format!("Failed to execute npm install: {}", e)),
-                }
-            }
+    let client = net::client();
+    let tree = match Resolver::new(client.clone())
+        .in_dir(&root)
+        .with_lockfile(&lockfile)
+        .force(options.force_mode)
+        .legacy_peer_deps(legacy_peer_deps)
+        .platform(Platform::from_config(&config))
+        .overrides(overrides)
+        .cache_mode(cache_mode)
+        .resolve_workspaces(&registry_packages, &optional_dependencies, &members)
+    {
+        Ok(tree) => tree,
+        Err(e) => {
+            logger::error(&format!("Failed to resolve dependencies: {}", e));
+            std::process::exit(1);
+        }
+    };
 
-            if let Err(e) = lockfile.lock().unwrap().save() {
-                logger::error(&format!("Failed to save lockfile: {}", e));
-            }
-        });
-        handles.push(handle);
+    if options.debug_mode {
+        for index in 1..tree.nodes.len() {
+            let node = &tree.nodes[index];
+            logger::info(&format!(
+                "  {} -> {}@{}{}",
+                tree.location_key(index),
+                node.name,
+                node.version,
+                node.overridden_by.as_ref().map(|rule| format!(" ({})", rule)).unwrap_or_default()
+            ));
+        }
+        for (name, spec, reason) in &tree.skipped {
+            logger::info(&format!("  Skipped optional {}@{}: {}", name, spec, reason));
+        }
     }
 
-    if let Some(deps) = dependencies {
-        for (package, version) in deps {
-            let version_str = version.as_str().unwrap().trim_matches('"');
-            let cache_path = cache_dir.join(format!("{}_{}", package, version_str));
-            if let Err(e) = tx.send((package.clone(), version_str.to_string(), cache_path, false)) {
-                logger::error(&format!("Failed to queue package for installation: {}", e));
+    if !legacy_peer_deps {
+        let problems = tree.peer_problems();
+        if !problems.is_empty() {
+            tree.report_peer_problems(&problems, strict_peer_deps);
+            if strict_peer_deps {
+                logger::error("Unmet peer dependencies. Run without --strict-peer-deps, or with --legacy-peer-deps to ignore them.");
+                std::process::exit(1);
             }
         }
     }
 
-    if let Some(dev_deps) = dev_dependencies {
-        for (package, version) in dev_deps {
-            let version_str = version.as_str().unwrap().trim_matches('"');
-            let cache_path = cache_dir.join(format!("{}_{}", package, version_str));
-            if let Err(e) = tx.send((package.clone(), version_str.to_string(), cache_path, true)) {
-                logger::error(&format!("Failed to queue package for installation: {}", e));
-            }
-        }
+    if !engines::check(&tree, &json, engine_strict) {
+        logger::error("Unsupported engines. Run without --engine-strict to install anyway.");
+        std::process::exit(1);
+    }
+
+    installer::prune(&root, &lockfile, &tree);
+
+    if let Err(e) = installer::install_tree(&client, &tree, &root, cache_mode) {
+        logger::error(&format!("Failed to install packages: {}", e));
+        std::process::exit(1);
     }
 
-    drop(tx);
+    tree.write_lockfile(&mut lockfile);
 
-    for handle in handles {
-        if let Err(e) = handle.join() {
-            logger::error(&format!("A worker thread panicked: {:?}", e));
+    let dev_dependencies = json.get("devDependencies").and_then(|d| d.as_object());
+    for (name, version) in npm_packages.into_iter().chain(tree.unresolved.iter().cloned()) {
+        let is_dev = dev_dependencies.is_some_and(|deps| deps.contains_key(&name));
+        install_with_npm(&root, &name, &version, is_dev, cache_mode);
+        lockfile.insert(
+            format!("node_modules/{}", name),
+            LockFileEntry {
+                name: name.clone(),
+                version: version.clone(),
+                use_npm_fallback: true,
+                resolved_version: version,
+                ..Default::default()
+            },
+        );
+    }
+
+    if let Err(e) = lockfile.save() {
+        logger::error(&format!("Failed to save lockfile: {}", e));
+    }
+
+    if config.get_bool("cache-auto-prune") {
+        let pruned = gc::prune(gc::Limits::from_config(&config));
+        if pruned.removed > 0 {
+            logger::info(&format!(
+                "Pruned {} unused cache entr{} from the cache, freeing {}",
+                pruned.removed,
+                if pruned.removed == 1 { "y" } else { "ies" },
+                gc::format_size(pruned.freed)
+            ));
         }
     }
 
     println!();
     logger::info("All packages have been installed successfully.");
 }
+
+fn install_with_npm(root: &Path, name: &str, version: &str, is_dev: bool, cache_mode: CacheMode) {
+    logger::info(&format!("Installing {} using npm...", name));
+
+    let package_spec = format!("{}@{}", name, version);
+    let (shell, shell_arg) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+
+    match std::process::Command::new(shell)
+        .arg(shell_arg)
+        .arg(format!(
+            "npm install {}{}{}",
+            if is_dev { "--save-dev " } else { "" },
+            match cache_mode {
+                CacheMode::Online => "",
+                CacheMode::PreferOffline => "--prefer-offline ",
+                CacheMode::Offline => "--offline ",
+            },
+            package_spec
+        ))
+        .current_dir(PathBuf::from(root))
+        .status()
+    {
+        Ok(status) => {
+            if status.success() {
+                logger::info(&format!(
+                    "Successfully installed {} using npm",
+                    package_spec
+                ));
+            } else {
+                logger::error(&format!(
+                    "npm install failed for {} with exit code: {}",
+                    package_spec,
+                    status.code().unwrap_or(-1)
+                ));
+            }
+        }
+        Err(e) => logger::error(&format!("Failed to execute npm install: {}", e)),
+    }
+}
@@ -0,0 +1,400 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::env;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde_json::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+use tar::{Builder, Header};
+use crate::{logger, io::net};
+use crate::io::logger::colorize;
+use crate::runtime::resolver;
+use crate::utils::config::Config;
+use crate::utils::utils::{self, matches_path_pattern};
+use crate::utils::workspace;
+
+/// Files npm never publishes, whatever `files` or `.npmignore` say.
+const ALWAYS_IGNORED: [&str; 8] = [
+    ".git",
+    "node_modules",
+    ".npmrc",
+    "snpm.lockd",
+    "package-lock.json",
+    ".DS_Store",
+    "npm-debug.log",
+    ".npmignore",
+];
+
+/// A packed tarball and the manifest that went into it.
+pub struct Packed {
+    pub manifest: Value,
+    pub file_name: String,
+    pub files: Vec<PathBuf>,
+    /// Packages included from `node_modules` for `bundleDependencies`.
+    pub bundled: Vec<String>,
+    pub data: Vec<u8>,
+    pub integrity: String,
+    pub shasum: String,
+}
+
+/// `snpm pack`: writes `<name>-<version>.tgz` for the current package.
+pub fn pack(dry_run: bool) {
+    let packed = match pack_current() {
+        Ok(packed) => packed,
+        Err(e) => {
+            logger::error(&format!("Failed to pack: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    print_summary(&packed);
+
+    if dry_run {
+        return;
+    }
+    if let Err(e) = fs::write(&packed.file_name, &packed.data) {
+        logger::error(&format!("Failed to write {}: {}", packed.file_name, e));
+        std::process::exit(1);
+    }
+    logger::info(&format!("Wrote {}", colorize("green", &packed.file_name)));
+}
+
+/// `snpm publish`: packs the current package and uploads it to the
+/// configured registry under `tag`.
+pub fn publish(tag: &str, access: Option<&str>, dry_run: bool) {
+    let packed = match pack_current() {
+        Ok(packed) => packed,
+        Err(e) => {
+            logger::error(&format!("Failed to pack: {}", e));
+            std::process::exit(1);
+        }
+    };
+
+    print_summary(&packed);
+
+    if packed.manifest.get("private").and_then(|p| p.as_bool()) == Some(true) {
+        logger::error("This package has been marked as private. Remove the 'private' field to publish it.");
+        std::process::exit(1);
+    }
+
+    let name = packed.manifest["name"].as_str().unwrap_or_default().to_string();
+    let version = packed.manifest["version"].as_str().unwrap_or_default().to_string();
+
+    if dry_run {
+        logger::info(&format!("Would publish {}@{} with tag {} (dry run)", name, version, tag));
+        return;
+    }
+
+    let config = Config::load();
+    let registry = config.registry();
+    let tarball_url = format!("{}{}/-/{}", registry, name, tarball_base_name(&name, &version));
+
+    let mut version_manifest = packed.manifest.clone();
+    version_manifest["_id"] = Value::String(format!("{}@{}", name, version));
+    version_manifest["dist"] = serde_json::json!({
+        "integrity": packed.integrity,
+        "shasum": packed.shasum,
+        "tarball": tarball_url,
+    });
+
+    let mut document = serde_json::json!({
+        "_id": name,
+        "name": name,
+        "description": packed.manifest.get("description"),
+        "dist-tags": { tag: version },
+        "versions": { version.clone(): version_manifest },
+        "_attachments": {
+            tarball_base_name(&name, &version): {
+                "content_type": "application/octet-stream",
+                "data": STANDARD.encode(&packed.data),
+                "length": packed.data.len(),
+            }
+        },
+    });
+    if let Some(access) = access {
+        document["access"] = Value::String(access.to_string());
+    }
+
+    match net::publish_package(&net::client(), &name, &document, config.auth_token(&registry)) {
+        Ok(()) => logger::info(&format!("Published {}@{}", colorize("green", &name), version)),
+        Err(e) => {
+            logger::error(&format!("Failed to publish {}@{}: {}", name, version, e));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn pack_current() -> Result<Packed, Box<dyn std::error::Error>> {
+    let current_dir = env::current_dir()?;
+    let mut manifest = utils::read_json(&current_dir.join("package.json"))?;
+
+    let name = manifest
+        .get("name")
+        .and_then(|n| n.as_str())
+        .ok_or("package.json has no name")?
+        .to_string();
+    let version = manifest
+        .get("version")
+        .and_then(|v| v.as_str())
+        .ok_or("package.json has no version")?
+        .to_string();
+
+    if let Some(root) = workspace::find_root(&current_dir) {
+        let root_json = utils::read_json(&root.join("package.json"))?;
+        let members = workspace::load_members(&root, &root_json);
+        workspace::rewrite_workspace_ranges(&mut manifest, &members)?;
+    }
+
+    let mut files = collect_files(&current_dir, &manifest)?;
+    let bundled = collect_bundled(&current_dir, &manifest, &mut files)?;
+    let data = build_tarball(&current_dir, &files, &manifest)?;
+
+    let integrity = format!("sha512-{}", STANDARD.encode(Sha512::digest(&data)));
+    let shasum = Sha1::digest(&data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    Ok(Packed {
+        file_name: tarball_base_name(&name, &version),
+        manifest,
+        files,
+        bundled,
+        data,
+        integrity,
+        shasum,
+    })
+}
+
+/// `@scope/name` at `1.0.0` packs to `scope-name-1.0.0.tgz`.
+fn tarball_base_name(name: &str, version: &str) -> String {
+    format!("{}-{}.tgz", name.trim_start_matches('@').replace('/', "-"), version)
+}
+
+fn print_summary(packed: &Packed) {
+    println!(
+        "{} {}@{}",
+        colorize("magenta", "package:"),
+        packed.manifest["name"].as_str().unwrap_or_default(),
+        packed.manifest["version"].as_str().unwrap_or_default()
+    );
+    println!("{}", colorize("magenta", "contents:"));
+    for file in &packed.files {
+        println!("  {}", file.to_string_lossy().replace('\\', "/"));
+    }
+    if !packed.bundled.is_empty() {
+        println!("{}", colorize("magenta", "bundled deps:"));
+        for name in &packed.bundled {
+            println!("  {}", name);
+        }
+    }
+    println!("{} {}", colorize("magenta", "filename:"), packed.file_name);
+    println!("{} {} bytes", colorize("magenta", "package size:"), packed.data.len());
+    println!("{} {}", colorize("magenta", "shasum:"), packed.shasum);
+    println!("{} {}", colorize("magenta", "integrity:"), packed.integrity);
+    println!("{} {}", colorize("magenta", "total files:"), packed.files.len());
+    println!();
+}
+
+/// Picks the files to publish: the `files` field when present, otherwise
+/// everything not excluded by `.npmignore` (or `.gitignore`). package.json,
+/// the README, the license and `main` are always included.
+fn collect_files(dir: &Path, manifest: &Value) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut all = vec![];
+    walk(dir, dir, &mut all)?;
+
+    let ignore_file = [".npmignore", ".gitignore"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file());
+    let ignore_patterns: Vec<String> = ignore_file
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|content| {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let listed: Option<Vec<String>> = manifest.get("files").and_then(|f| f.as_array()).map(|entries| {
+        entries
+            .iter()
+            .filter_map(|e| e.as_str())
+            .map(|e| e.trim_start_matches("./").trim_end_matches('/').to_string())
+            .collect()
+    });
+    let main = manifest
+        .get("main")
+        .and_then(|m| m.as_str())
+        .map(|m| m.trim_start_matches("./").to_string());
+
+    let mut files: Vec<PathBuf> = all
+        .into_iter()
+        .filter(|path| {
+            let relative = path.to_string_lossy().replace('\\', "/");
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let upper = file_name.to_uppercase();
+
+            if relative == "package.json"
+                || upper.starts_with("README")
+                || upper.starts_with("LICENSE")
+                || upper.starts_with("LICENCE")
+                || main.as_deref() == Some(relative.as_str())
+            {
+                return true;
+            }
+
+            match &listed {
+                Some(listed) => listed.iter().any(|entry| {
+                    relative == *entry
+                        || relative.starts_with(&format!("{}/", entry))
+                        || matches_path_pattern(entry, &relative)
+                }),
+                None => !is_ignored(&relative, &ignore_patterns),
+            }
+        })
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+/// Adds the installed packages named in `bundleDependencies` to `files`,
+/// together with everything they depend on, whatever `files` or
+/// `.npmignore` say. Returns the bundled package names.
+fn collect_bundled(
+    dir: &Path,
+    manifest: &Value,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let node_modules = dir.join("node_modules");
+    let mut queue: Vec<(PathBuf, String, bool)> = resolver::bundled_dependencies_of(manifest)
+        .into_iter()
+        .map(|name| (node_modules.clone(), name, true))
+        .collect();
+    let mut seen: Vec<PathBuf> = vec![];
+    let mut bundled = vec![];
+
+    while let Some((from, name, required)) = queue.pop() {
+        // Like node, look in the nested node_modules first, then hoisted.
+        let Some(package_dir) = [from.join(&name), node_modules.join(&name)]
+            .into_iter()
+            .find(|path| path.join("package.json").is_file())
+        else {
+            if required {
+                return Err(format!("Bundled dependency '{}' is not installed. Run `snpm install` first.", name).into());
+            }
+            continue;
+        };
+        if seen.contains(&package_dir) {
+            continue;
+        }
+        seen.push(package_dir.clone());
+        bundled.push(name);
+
+        walk(dir, &package_dir, files)?;
+
+        let package_manifest = utils::read_json(&package_dir.join("package.json"))?;
+        let nested = package_dir.join("node_modules");
+        let dependencies = resolver::dependencies_of(&package_manifest)
+            .into_iter()
+            .chain(resolver::optional_dependencies_of(&package_manifest));
+        for (dependency, _) in dependencies {
+            queue.push((nested.clone(), dependency, false));
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    bundled.sort();
+    Ok(bundled)
+}
+
+fn is_ignored(relative: &str, patterns: &[String]) -> bool {
+    let mut ignored = false;
+
+    for pattern in patterns {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern.as_str()),
+        };
+        let anchored = pattern.starts_with('/');
+        let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+
+        let segments: Vec<&str> = relative.split('/').collect();
+        let matched = if anchored || pattern.contains('/') {
+            (1..=segments.len()).any(|n| matches_path_pattern(pattern, &segments[..n].join("/")))
+        } else {
+            segments.iter().any(|segment| matches_path_pattern(pattern, segment))
+        };
+
+        if matched {
+            ignored = !negated;
+        }
+    }
+
+    ignored
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if ALWAYS_IGNORED.contains(&name.as_str()) || name.ends_with(".tgz") {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            walk(root, &path, files)?;
+        } else if entry.file_type()?.is_file() {
+            files.push(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn build_tarball(dir: &Path, files: &[PathBuf], manifest: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    // npm stamps every entry with the same date so that packing is
+    // reproducible.
+    const MTIME: u64 = 499162500;
+
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+    for file in files {
+        let relative = file.to_string_lossy().replace('\\', "/");
+        let data = if relative == "package.json" {
+            format!("{}\n", serde_json::to_string_pretty(manifest)?).into_bytes()
+        } else {
+            fs::read(dir.join(file))?
+        };
+
+        let executable = is_executable(&dir.join(file));
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(if executable { 0o755 } else { 0o644 });
+        header.set_mtime(MTIME);
+        header.set_entry_type(tar::EntryType::Regular);
+        builder.append_data(&mut header, format!("package/{}", relative), data.as_slice())?;
+    }
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
@@ -0,0 +1,1206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::{logger, io::net};
+use crate::io::net::Client;
+use crate::io::cache::{self, CacheMode};
+use crate::runtime::sources;
+use crate::utils::lockfile::{LockFile, LockFileEntry};
+use crate::utils::overrides::{Applied, Overrides};
+use crate::utils::platform::Platform;
+use crate::utils::range;
+use crate::utils::spec::Spec;
+use crate::utils::utils;
+use crate::utils::workspace::Member;
+
+/// A package placed in the dependency tree. Index 0 of a [`Tree`] is the
+/// root project, which has no manifest of its own. Workspace members and
+/// `link:` dependencies are placed as nodes with a `link` to their directory
+/// relative to the root. Git, tarball and `file:` dependencies carry the
+/// `source` directory they are copied from and what they `resolved` to.
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    pub name: String,
+    pub version: String,
+    pub spec: String,
+    pub manifest: Value,
+    pub parent: Option<usize>,
+    pub children: BTreeMap<String, usize>,
+    pub link: Option<PathBuf>,
+    pub source: Option<PathBuf>,
+    pub resolved: Option<String>,
+    /// Integrity of a tarball URL dependency, which has no registry `dist`.
+    pub integrity: Option<String>,
+    /// Only reached through `optionalDependencies`, so failing to install it
+    /// is not an error.
+    pub optional: bool,
+    /// The package whose dependency introduced this one, which can differ
+    /// from `parent` once hoisted. Overrides match against this chain.
+    pub requested_by: Option<usize>,
+    /// The override rule that replaced the requested spec, if any.
+    pub overridden_by: Option<String>,
+}
+
+/// A hoisted `node_modules` layout: every package sits at the top level
+/// unless a different version already occupies that name, in which case it
+/// is nested under the package that needs it.
+///
+/// Direct dependencies that could not be resolved from the registry are
+/// collected in `unresolved` rather than failing the whole tree. Optional
+/// dependencies that failed or don't support the platform are left out and
+/// listed in `skipped` with the reason.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+    pub unresolved: Vec<(String, String)>,
+    pub skipped: Vec<(String, String, String)>,
+}
+
+impl Tree {
+    fn new() -> Self {
+        Tree {
+            nodes: vec![Node::default()],
+            unresolved: vec![],
+            skipped: vec![],
+        }
+    }
+
+    /// Location of a node relative to the project root, such as
+    /// `node_modules/a/node_modules/b`. The root itself is the empty path.
+    pub fn location(&self, index: usize) -> PathBuf {
+        let mut names = vec![];
+        let mut current = Some(index);
+        while let Some(i) = current {
+            if i == 0 {
+                break;
+            }
+            names.push(self.nodes[i].name.as_str());
+            current = self.nodes[i].parent;
+        }
+
+        let mut path = PathBuf::new();
+        for name in names.iter().rev() {
+            path.push("node_modules");
+            path.push(name);
+        }
+        path
+    }
+
+    /// [`Tree::location`] with `/` separators, as used for lockfile keys.
+    pub fn location_key(&self, index: usize) -> String {
+        self.location(index).to_string_lossy().replace('\\', "/")
+    }
+
+    /// Depth of a node below the root, which is depth 0.
+    pub fn depth(&self, index: usize) -> usize {
+        let mut depth = 0;
+        let mut current = self.nodes[index].parent;
+        while let Some(i) = current {
+            depth += 1;
+            current = self.nodes[i].parent;
+        }
+        depth
+    }
+
+    /// Finds the copy of `name` that `from` would load, following Node's
+    /// module resolution up the tree.
+    fn lookup(&self, from: usize, name: &str) -> Option<usize> {
+        let mut current = Some(from);
+        while let Some(i) = current {
+            if let Some(&child) = self.nodes[i].children.get(name) {
+                return Some(child);
+            }
+            current = self.nodes[i].parent;
+        }
+        None
+    }
+
+    /// `(name, version)` of each package on the dependency path from the
+    /// root down to `index`, following `requested_by`.
+    pub fn chain(&self, index: usize) -> Vec<(String, String)> {
+        let mut chain = vec![];
+        let mut current = index;
+        while current != 0 {
+            let node = &self.nodes[current];
+            chain.push((node.name.clone(), node.version.clone()));
+            current = node.requested_by.unwrap_or(0);
+        }
+        chain.reverse();
+        chain
+    }
+
+    fn insert(&mut self, parent: usize, node: Node) -> usize {
+        let index = self.nodes.len();
+        self.nodes[parent].children.insert(node.name.clone(), index);
+        self.nodes.push(Node {
+            parent: Some(parent),
+            ..node
+        });
+        index
+    }
+
+    /// Marks `index` as no longer optional, along with the packages it pulls
+    /// in through regular dependencies that were only reached through
+    /// optional ones so far.
+    fn require(&mut self, index: usize) {
+        let mut pending = vec![index];
+        while let Some(i) = pending.pop() {
+            if !self.nodes[i].optional {
+                continue;
+            }
+            self.nodes[i].optional = false;
+            if self.nodes[i].link.is_some() {
+                continue;
+            }
+            let manifest = &self.nodes[i].manifest;
+            for (name, _) in unbundled(manifest, dependencies_of(manifest)) {
+                pending.extend(self.lookup(i, &name));
+            }
+        }
+    }
+
+    /// Peer dependencies that are missing or whose installed version does not
+    /// satisfy the range. Optional peers only count when they are present.
+    pub fn peer_problems(&self) -> Vec<PeerProblem> {
+        let mut problems = vec![];
+
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            for (name, range, optional) in peer_dependencies_of(&node.manifest) {
+                let found = self.lookup(index, &name);
+                let satisfied = match found {
+                    Some(found) => {
+                        let found = &self.nodes[found];
+                        found.link.is_some() || range::satisfies(&found.version, &range)
+                    }
+                    None => optional,
+                };
+                if !satisfied {
+                    problems.push(PeerProblem {
+                        requester: index,
+                        name,
+                        range,
+                        found,
+                    });
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Nodes that declare a dependency resolving to `index`, with the
+    /// declared spec and whether it is a peer dependency.
+    fn dependents(&self, index: usize) -> Vec<(usize, String, bool)> {
+        let name = &self.nodes[index].name;
+
+        (0..self.nodes.len())
+            .filter(|&i| i != index && self.lookup(i, name) == Some(index))
+            .filter_map(|i| {
+                let node = &self.nodes[i];
+                let declared = if i == 0 || node.link.is_some() {
+                    all_dependencies_of(&node.manifest)
+                } else {
+                    dependencies_of(&node.manifest)
+                };
+                if let Some((_, spec)) = declared.into_iter().find(|(dep, _)| dep == name) {
+                    return Some((i, spec, false));
+                }
+                peer_dependencies_of(&node.manifest)
+                    .into_iter()
+                    .find(|(dep, _, _)| dep == name)
+                    .map(|(_, range, _)| (i, range, true))
+            })
+            .collect()
+    }
+
+    fn describe(&self, index: usize) -> String {
+        if index == 0 {
+            return "the root project".to_string();
+        }
+        let node = &self.nodes[index];
+        format!("{}@{} ({})", node.name, node.version, self.location_key(index))
+    }
+
+    /// Prints who requires `index`, and who requires them, as a tree.
+    fn print_dependents(&self, index: usize, indent: usize, seen: &mut Vec<usize>) {
+        const MAX_DEPTH: usize = 6;
+        const MAX_SHOWN: usize = 5;
+
+        if index == 0 || indent >= MAX_DEPTH || seen.contains(&index) {
+            return;
+        }
+        seen.push(index);
+
+        let dependents = self.dependents(index);
+        for (dependent, spec, peer) in dependents.iter().take(MAX_SHOWN) {
+            eprintln!(
+                "{}└─ required by {} as {}\"{}\"",
+                "   ".repeat(indent + 1),
+                self.describe(*dependent),
+                if *peer { "peer " } else { "" },
+                spec
+            );
+            self.print_dependents(*dependent, indent + 1, seen);
+        }
+        if dependents.len() > MAX_SHOWN {
+            eprintln!("{}   and {} more", "   ".repeat(indent + 1), dependents.len() - MAX_SHOWN);
+        }
+    }
+
+    /// Reports each peer problem with the chain of packages that led to the
+    /// requester and, for conflicts, to the version that was installed.
+    pub fn report_peer_problems(&self, problems: &[PeerProblem], strict: bool) {
+        for problem in problems {
+            let requester = &self.nodes[problem.requester];
+            let summary = match problem.found {
+                Some(_) => format!(
+                    "Conflicting peer dependency: {}@{} requires {}@\"{}\"",
+                    requester.name, requester.version, problem.name, problem.range
+                ),
+                None => format!(
+                    "Missing peer dependency: {}@{} requires {}@\"{}\"",
+                    requester.name, requester.version, problem.name, problem.range
+                ),
+            };
+            if strict {
+                logger::error(&summary);
+            } else {
+                logger::warn(&summary);
+            }
+
+            eprintln!("   {}", self.describe(problem.requester));
+            self.print_dependents(problem.requester, 0, &mut vec![]);
+
+            match problem.found {
+                Some(found) => {
+                    eprintln!("   found {}", self.describe(found));
+                    self.print_dependents(found, 0, &mut vec![]);
+                }
+                None => eprintln!("   but no version of {} is installed", problem.name),
+            }
+        }
+    }
+
+    /// Records the tree in `lockfile`, replacing its previous contents.
+    pub fn write_lockfile(&self, lockfile: &mut LockFile) {
+        lockfile.clear();
+
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            lockfile.insert(
+                self.location_key(index),
+                LockFileEntry {
+                    name: node.name.clone(),
+                    version: node.spec.clone(),
+                    tarball_url: node.manifest["dist"]["tarball"].as_str().map(String::from),
+                    integrity: node.integrity.clone().or_else(|| cache::dist_integrity(&node.manifest["dist"])),
+                    use_npm_fallback: false,
+                    resolved_version: node.version.clone(),
+                    resolved: node.resolved.clone(),
+                    dependencies: unbundled(&node.manifest, dependencies_of(&node.manifest)).into_iter().collect(),
+                    optional_dependencies: unbundled(&node.manifest, optional_dependencies_of(&node.manifest))
+                        .into_iter()
+                        .collect(),
+                    peer_dependencies: peer_dependencies_of(&node.manifest)
+                        .into_iter()
+                        .map(|(name, range, _)| (name, range))
+                        .collect(),
+                    optional_peers: peer_dependencies_of(&node.manifest)
+                        .into_iter()
+                        .filter(|(_, _, optional)| *optional)
+                        .map(|(name, _, _)| name)
+                        .collect(),
+                    optional: node.optional,
+                    overridden_by: node.overridden_by.clone(),
+                    engines: specs_of(&node.manifest, "engines").into_iter().collect(),
+                    os: platform_field(&node.manifest, "os"),
+                    cpu: platform_field(&node.manifest, "cpu"),
+                    libc: platform_field(&node.manifest, "libc"),
+                    link: node
+                        .link
+                        .as_ref()
+                        .map(|link| link.to_string_lossy().replace('\\', "/")),
+                },
+            );
+        }
+    }
+}
+
+/// A peer dependency of `requester` that is missing, or `found` at a version
+/// outside `range`.
+pub struct PeerProblem {
+    pub requester: usize,
+    pub name: String,
+    pub range: String,
+    pub found: Option<usize>,
+}
+
+/// How a queued dependency was declared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edge {
+    Regular,
+    Optional,
+    Peer,
+}
+
+pub struct Resolver {
+    client: Client,
+    root: PathBuf,
+    packuments: HashMap<String, Value>,
+    failures: HashMap<String, String>,
+    locked: HashMap<String, LockFileEntry>,
+    force_mode: bool,
+    legacy_peer_deps: bool,
+    platform: Platform,
+    overrides: Overrides,
+    cache_mode: CacheMode,
+}
+
+impl Resolver {
+    pub fn new(client: Client) -> Self {
+        Resolver {
+            client,
+            root: env::current_dir().unwrap_or_default(),
+            packuments: HashMap::new(),
+            failures: HashMap::new(),
+            locked: HashMap::new(),
+            force_mode: false,
+            legacy_peer_deps: false,
+            platform: Platform::current(),
+            overrides: Overrides::default(),
+            cache_mode: CacheMode::Online,
+        }
+    }
+
+    /// Directory that relative `file:` and `link:` specs of the root's
+    /// dependencies are resolved against. Defaults to the current directory.
+    pub fn in_dir(mut self, root: &Path) -> Self {
+        self.root = root.to_path_buf();
+        self
+    }
+
+    /// With `force_mode`, a range that matches nothing falls back to the
+    /// `latest` dist-tag instead of failing.
+    pub fn force(mut self, force_mode: bool) -> Self {
+        self.force_mode = force_mode;
+        self
+    }
+
+    /// With `legacy_peer_deps`, peer dependencies are not installed
+    /// automatically, as with npm 6 and earlier.
+    pub fn legacy_peer_deps(mut self, legacy_peer_deps: bool) -> Self {
+        self.legacy_peer_deps = legacy_peer_deps;
+        self
+    }
+
+    /// Platform that `os`, `cpu` and `libc` fields are checked against.
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Rules from the root's `overrides` and `resolutions` that replace the
+    /// spec of matching dependencies.
+    pub fn overrides(mut self, overrides: Overrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Whether metadata, git repositories and tarballs in the cache are used
+    /// without asking the network.
+    pub fn cache_mode(mut self, cache_mode: CacheMode) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
+    /// Prefers the versions recorded in `lockfile` wherever they still
+    /// satisfy the requested range, which also skips their metadata fetch.
+    pub fn with_lockfile(mut self, lockfile: &LockFile) -> Self {
+        self.locked = lockfile
+            .entries()
+            .filter(|(_, entry)| !entry.use_npm_fallback && (entry.tarball_url.is_some() || entry.resolved.is_some()))
+            .map(|(location, entry)| (location.clone(), entry.clone()))
+            .collect();
+        self
+    }
+
+    pub fn resolve(&mut self, dependencies: &[(String, String)]) -> Result<Tree, Box<dyn std::error::Error>> {
+        self.resolve_workspaces(dependencies, &[], &[])
+    }
+
+    /// Resolves the root's `dependencies` together with those of every
+    /// workspace member into one tree. Members are linked at the top level,
+    /// and packages are processed breadth first so that shallower packages
+    /// claim the top-level slots before deeper ones.
+    ///
+    /// Missing non-optional peer dependencies are installed next to the
+    /// package that needs them unless `legacy_peer_deps` is set. A peer that
+    /// is already present is kept even if it doesn't match; see
+    /// [`Tree::peer_problems`].
+    ///
+    /// Optional dependencies, and everything they depend on, are skipped
+    /// when they fail to resolve or don't support the platform. Other
+    /// packages that don't support it fail the resolution unless
+    /// `force_mode` is set.
+    pub fn resolve_workspaces(
+        &mut self,
+        dependencies: &[(String, String)],
+        optional_dependencies: &[(String, String)],
+        workspaces: &[Member],
+    ) -> Result<Tree, Box<dyn std::error::Error>> {
+        let mut tree = Tree::new();
+        tree.nodes[0].manifest = serde_json::json!({
+            "dependencies": dependencies.iter().cloned().collect::<BTreeMap<String, String>>(),
+        });
+
+        let mut level: Vec<(usize, String, String, Edge)> = dependencies
+            .iter()
+            .map(|(name, spec)| (0, name.clone(), spec.clone(), Edge::Regular))
+            .chain(
+                optional_dependencies
+                    .iter()
+                    .map(|(name, spec)| (0, name.clone(), spec.clone(), Edge::Optional)),
+            )
+            .collect();
+
+        for workspace in workspaces {
+            let index = tree.insert(
+                0,
+                Node {
+                    name: workspace.name.clone(),
+                    version: workspace.version.clone(),
+                    spec: format!("workspace:{}", workspace.version),
+                    manifest: workspace.manifest.clone(),
+                    link: Some(workspace.path.clone()),
+                    ..Default::default()
+                },
+            );
+            for (name, spec) in all_dependencies_of(&workspace.manifest) {
+                level.push((index, name, spec, Edge::Regular));
+            }
+            for (name, spec) in optional_dependencies_of(&workspace.manifest) {
+                level.push((index, name, spec, Edge::Optional));
+            }
+            level.extend(self.peers_to_install(&tree, index));
+        }
+
+        while !level.is_empty() {
+            let wanted: Vec<(String, String)> = level
+                .iter()
+                .filter(|(dependent, name, raw, _)| {
+                    // Never ask the registry about a workspace member's name.
+                    !tree.lookup(*dependent, name).is_some_and(|existing| {
+                        let node = &tree.nodes[existing];
+                        is_workspace(node) && reusable(node, &Spec::parse(name, raw), raw)
+                    })
+                })
+                .filter_map(|(_, name, raw, _)| self.registry_spec(name, raw))
+                .collect();
+            self.prefetch(wanted);
+
+            let mut next_level = vec![];
+            for (dependent, name, raw, edge) in level {
+                // Regular dependencies of an optional package are optional
+                // too, unless it has since been reached through a regular one.
+                let edge = if edge == Edge::Regular && tree.nodes[dependent].optional {
+                    Edge::Optional
+                } else {
+                    edge
+                };
+                let (raw, overridden_by) = match self.find_override(&tree, dependent, &name, &raw) {
+                    Some(applied) => (applied.spec, Some(applied.label)),
+                    None => (raw, None),
+                };
+                let spec = Spec::parse(&name, &raw);
+
+                if let Some(existing) = tree.lookup(dependent, &name) {
+                    if edge == Edge::Peer || reusable(&tree.nodes[existing], &spec, &raw) {
+                        if edge == Edge::Regular {
+                            tree.require(existing);
+                        }
+                        continue;
+                    }
+                }
+
+                if let Spec::Workspace { .. } = spec {
+                    return Err(format!("No workspace package named '{}' ({})", name, raw).into());
+                }
+
+                let parent = if tree.nodes[0].children.contains_key(&name) {
+                    dependent
+                } else {
+                    0
+                };
+
+                if tree.nodes[parent].children.contains_key(&name) {
+                    logger::warn(&format!(
+                        "Conflicting versions of '{}' requested; keeping {}",
+                        name, tree.nodes[tree.nodes[parent].children[&name]].version
+                    ));
+                    continue;
+                }
+
+                let location = format!("{}node_modules/{}", location_prefix(&tree, parent), name);
+                let node = match &spec {
+                    Spec::Registry { name: package, range } => match self.locked_manifest(&location, &name, package, &raw, range, overridden_by.as_deref()) {
+                        Some(locked) => Ok(locked),
+                        None => self.select(package, range),
+                    }
+                    .map(|(version, manifest)| Node {
+                        name: name.clone(),
+                        version,
+                        spec: raw.clone(),
+                        manifest,
+                        ..Default::default()
+                    }),
+                    _ => {
+                        let base = self.package_dir(&tree, dependent);
+                        self.fetch_source(&location, &name, &raw, &spec, &base, overridden_by.as_deref())
+                    }
+                };
+
+                let node = match node {
+                    Ok(node) => node,
+                    Err(e) if edge == Edge::Optional => {
+                        tree.skipped.push((name, raw, e.to_string()));
+                        continue;
+                    }
+                    Err(e) if edge == Edge::Peer => {
+                        logger::warn(&format!("Could not install peer dependency {}@{}: {}", name, raw, e));
+                        continue;
+                    }
+                    // Falling back to npm needs the network, so offline
+                    // installs fail instead.
+                    Err(e)
+                        if self.cache_mode != CacheMode::Offline
+                            && (tree.nodes[dependent].parent.is_none() || tree.nodes[dependent].link.is_some()) =>
+                    {
+                        logger::error(&e.to_string());
+                        tree.unresolved.push((name, raw));
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                let node = match self.platform.check(&node.manifest) {
+                    _ if node.link.is_some() => node,
+                    Ok(()) => node,
+                    Err(e) if edge == Edge::Optional => {
+                        tree.skipped.push((name, raw, e));
+                        continue;
+                    }
+                    Err(e) if self.force_mode => {
+                        logger::warn(&e);
+                        node
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let node = Node {
+                    optional: edge == Edge::Optional,
+                    requested_by: Some(dependent),
+                    overridden_by,
+                    ..node
+                };
+
+                // Linked directories manage their own dependencies.
+                let linked = node.link.is_some();
+                let index = tree.insert(parent, node);
+                if !linked {
+                    let manifest = &tree.nodes[index].manifest;
+                    for (dep_name, dep_spec) in unbundled(manifest, dependencies_of(manifest)) {
+                        next_level.push((index, dep_name, dep_spec, Edge::Regular));
+                    }
+                    for (dep_name, dep_spec) in unbundled(manifest, optional_dependencies_of(manifest)) {
+                        next_level.push((index, dep_name, dep_spec, Edge::Optional));
+                    }
+                    next_level.extend(self.peers_to_install(&tree, index));
+                }
+            }
+
+            level = next_level;
+        }
+
+        Ok(tree)
+    }
+
+    /// The override for dependency `name` of `dependent`. Workspace
+    /// dependencies are never overridden.
+    fn find_override(&mut self, tree: &Tree, dependent: usize, name: &str, raw: &str) -> Option<Applied> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+
+        let original = Spec::parse(name, raw);
+        if let Spec::Workspace { .. } = original {
+            return None;
+        }
+
+        let overrides = self.overrides.clone();
+        overrides.find(&tree.chain(dependent), name, || match &original {
+            Spec::Registry { name: package, range } => self.select(package, range).ok().map(|(version, _)| version),
+            _ => None,
+        })
+    }
+
+    /// The non-optional peers of a node, queued as dependencies of its parent
+    /// so that they land next to it.
+    fn peers_to_install(&self, tree: &Tree, index: usize) -> Vec<(usize, String, String, Edge)> {
+        if self.legacy_peer_deps {
+            return vec![];
+        }
+
+        let parent = tree.nodes[index].parent.unwrap_or(0);
+        peer_dependencies_of(&tree.nodes[index].manifest)
+            .into_iter()
+            .filter(|(_, _, optional)| !optional)
+            .map(|(name, range, _)| (parent, name, range, Edge::Peer))
+            .collect()
+    }
+
+    /// Directory of the package that declared a dependency, which relative
+    /// `file:` and `link:` specs are resolved against.
+    fn package_dir(&self, tree: &Tree, index: usize) -> PathBuf {
+        let node = &tree.nodes[index];
+        if let Some(link) = &node.link {
+            self.root.join(link)
+        } else if let Some(source) = &node.source {
+            source.clone()
+        } else {
+            self.root.join(tree.location(index))
+        }
+    }
+
+    /// Fetches a git, tarball, `file:` or `link:` dependency. A git
+    /// dependency whose spec is unchanged since the lockfile was written is
+    /// fetched at the locked commit.
+    fn fetch_source(
+        &self,
+        location: &str,
+        name: &str,
+        raw: &str,
+        spec: &Spec,
+        base: &Path,
+        overridden_by: Option<&str>,
+    ) -> Result<Node, Box<dyn std::error::Error>> {
+        let entry = self
+            .locked
+            .get(location)
+            .filter(|entry| entry.name == name && entry.version == raw && entry.overridden_by.as_deref() == overridden_by);
+        let locked = entry
+            .and_then(|entry| entry.resolved.as_deref())
+            .map(|resolved| Spec::parse(name, resolved))
+            .filter(|locked| matches!((spec, locked), (Spec::Git { .. }, Spec::Git { .. })));
+        let integrity = entry
+            .filter(|_| matches!(spec, Spec::Tarball { .. }))
+            .and_then(|entry| entry.integrity.as_deref());
+
+        let fetched = sources::fetch(&self.client, locked.as_ref().unwrap_or(spec), base, self.cache_mode, integrity)
+            .map_err(|e| format!("Failed to fetch {}@{}: {}", name, raw, e))?;
+
+        let version = fetched
+            .manifest
+            .get("version")
+            .and_then(|v| v.as_str())
+            .unwrap_or("0.0.0")
+            .to_string();
+        let (link, source) = if fetched.link {
+            (Some(utils::relative_path(&self.root, &fetched.dir)), None)
+        } else {
+            (None, Some(fetched.dir))
+        };
+
+        Ok(Node {
+            name: name.to_string(),
+            version,
+            spec: raw.to_string(),
+            manifest: fetched.manifest,
+            link,
+            source,
+            resolved: Some(fetched.resolved),
+            integrity: fetched.integrity,
+            ..Default::default()
+        })
+    }
+
+    fn is_locked(&self, name: &str, package: &str, raw: &str, range: &str) -> bool {
+        self.locked
+            .values()
+            .any(|entry| entry.name == name && entry.tarball_url.is_some() && pins(entry, package, raw, range))
+    }
+
+    fn locked_manifest(
+        &self,
+        location: &str,
+        name: &str,
+        package: &str,
+        raw: &str,
+        range: &str,
+        overridden_by: Option<&str>,
+    ) -> Option<(String, Value)> {
+        let entry = self.locked.get(location)?;
+        if entry.name != name
+            || entry.overridden_by.as_deref() != overridden_by
+            || entry.link.is_some()
+            || entry.tarball_url.is_none()
+            || !pins(entry, package, raw, range)
+        {
+            return None;
+        }
+
+        let manifest = serde_json::json!({
+            "name": entry.name,
+            "version": entry.resolved_version,
+            "dependencies": entry.dependencies,
+            "optionalDependencies": entry.optional_dependencies,
+            "peerDependencies": entry.peer_dependencies,
+            "peerDependenciesMeta": entry
+                .optional_peers
+                .iter()
+                .map(|name| (name.clone(), serde_json::json!({ "optional": true })))
+                .collect::<serde_json::Map<String, Value>>(),
+            "engines": entry.engines,
+            "os": entry.os,
+            "cpu": entry.cpu,
+            "libc": entry.libc,
+            "dist": { "tarball": entry.tarball_url, "integrity": entry.integrity },
+        });
+        Some((entry.resolved_version.clone(), manifest))
+    }
+
+    /// Picks the version of `name` that `spec` refers to, fetching its
+    /// packument if it wasn't prefetched.
+    fn select(&mut self, name: &str, spec: &str) -> Result<(String, Value), Box<dyn std::error::Error>> {
+        if let Some(failure) = self.failures.get(name) {
+            return Err(failure.clone().into());
+        }
+        if !self.packuments.contains_key(name) {
+            let metadata = net::fetch_metadata(&self.client, name, self.cache_mode)?;
+            self.packuments.insert(name.to_string(), metadata);
+        }
+
+        let metadata = &self.packuments[name];
+        let version = match range::select_version(metadata, spec) {
+            Some(version) => version,
+            None if self.force_mode => {
+                let latest = range::select_version(metadata, "latest")
+                    .ok_or_else(|| format!("No versions available for package '{}'", name))?;
+                logger::warn(&format!(
+                    "Using latest version {} for package {} (requested {})",
+                    latest, name, spec
+                ));
+                latest
+            }
+            None => {
+                return Err(format!(
+                    "No version found matching {} for package '{}'. Use --force to install the latest version.",
+                    spec, name
+                )
+                .into())
+            }
+        };
+        let manifest = metadata["versions"][&version].clone();
+        if manifest.is_null() {
+            return Err(format!("Version {} of '{}' is missing from the registry", version, name).into());
+        }
+
+        Ok((version, manifest))
+    }
+
+    /// The registry package and range `raw` refers to, unless the lockfile
+    /// already pins it.
+    fn registry_spec(&self, name: &str, raw: &str) -> Option<(String, String)> {
+        match Spec::parse(name, raw) {
+            Spec::Registry { name: package, range } if !self.is_locked(name, &package, raw, &range) => Some((package, range)),
+            _ => None,
+        }
+    }
+
+    /// Fetches the packuments for `wanted` that are not already known, along
+    /// with those of the dependencies they are likely to lead to, without
+    /// waiting for the current level to resolve. Failures are kept and
+    /// reported when the package is actually needed.
+    fn prefetch(&mut self, wanted: Vec<(String, String)>) {
+        let known = |resolver: &Self, name: &str| {
+            resolver.packuments.contains_key(name) || resolver.failures.contains_key(name)
+        };
+        let wanted: Vec<(String, String)> = wanted.into_iter().filter(|(name, _)| !known(self, name)).collect();
+        if wanted.is_empty() {
+            return;
+        }
+
+        let fetched = net::fetch_metadata_pipelined(&self.client, wanted, self.cache_mode, |metadata, range| {
+            let Some(version) = range::select_version(metadata, range) else {
+                return vec![];
+            };
+            let manifest = &metadata["versions"][&version];
+            let mut dependencies = dependencies_of(manifest);
+            dependencies.extend(optional_dependencies_of(manifest));
+            unbundled(manifest, dependencies)
+                .into_iter()
+                .filter_map(|(name, raw)| self.registry_spec(&name, &raw))
+                .filter(|(name, _)| !known(self, name))
+                .collect()
+        });
+
+        for (name, result) in fetched {
+            match result {
+                Ok(metadata) => {
+                    self.packuments.insert(name, metadata);
+                }
+                Err(e) => {
+                    self.failures.insert(name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Whether an already placed node can serve a dependency on `spec`. Like
+/// npm, a workspace member also serves ordinary ranges on its name that its
+/// version satisfies, rather than a registry package of the same name.
+fn reusable(node: &Node, spec: &Spec, raw: &str) -> bool {
+    match spec {
+        Spec::Workspace { .. } => node.link.is_some(),
+        Spec::Registry { name, range } if is_workspace(node) => {
+            node.name == *name && range::satisfies(&node.version, range)
+        }
+        Spec::Registry { name, range } => {
+            let placed = Spec::parse(&node.name, &node.spec);
+            matches!(&placed, Spec::Registry { name: placed_name, .. } if placed_name == name)
+                && node.resolved.is_none()
+                && (node.spec == raw || range::satisfies(&node.version, range))
+        }
+        _ => node.spec == raw,
+    }
+}
+
+fn is_workspace(node: &Node) -> bool {
+    node.link.is_some() && node.spec.starts_with("workspace:")
+}
+
+/// Whether the locked `entry` still answers a dependency on `raw`, which
+/// asks the registry for `range` of `package`. An alias pointed at another
+/// package never does. Dist-tags like `latest` never satisfy a version, so
+/// an entry locked for the very same spec counts too.
+fn pins(entry: &LockFileEntry, package: &str, raw: &str, range: &str) -> bool {
+    let locked_package = match Spec::parse(&entry.name, &entry.version) {
+        Spec::Registry { name, .. } => name,
+        _ => return false,
+    };
+    locked_package == package && (entry.version == raw || range::satisfies(&entry.resolved_version, range))
+}
+
+fn location_prefix(tree: &Tree, index: usize) -> String {
+    if index == 0 {
+        String::new()
+    } else {
+        format!("{}/", tree.location_key(index))
+    }
+}
+
+/// The `dependencies` of a package manifest as `(name, spec)` pairs. Names
+/// that are also in `optionalDependencies` are left to
+/// [`optional_dependencies_of`], as npm lets the optional entry win.
+pub fn dependencies_of(manifest: &Value) -> Vec<(String, String)> {
+    specs_of(manifest, "dependencies")
+        .into_iter()
+        .filter(|(name, _)| !is_optional(manifest, name))
+        .collect()
+}
+
+/// The `optionalDependencies` of a package manifest as `(name, spec)` pairs.
+pub fn optional_dependencies_of(manifest: &Value) -> Vec<(String, String)> {
+    specs_of(manifest, "optionalDependencies")
+}
+
+/// The names in `bundleDependencies` (or `bundledDependencies`), which ship
+/// inside the package's own tarball. `true` bundles every dependency.
+pub fn bundled_dependencies_of(manifest: &Value) -> Vec<String> {
+    let bundled = manifest
+        .get("bundleDependencies")
+        .or_else(|| manifest.get("bundledDependencies"));
+
+    match bundled {
+        Some(Value::Bool(true)) => specs_of(manifest, "dependencies").into_iter().map(|(name, _)| name).collect(),
+        Some(Value::Array(names)) => names.iter().filter_map(|n| n.as_str().map(String::from)).collect(),
+        _ => vec![],
+    }
+}
+
+/// Drops the bundled dependencies of a package from `dependencies`. They are
+/// already in its `node_modules` and must not be resolved again.
+fn unbundled(manifest: &Value, dependencies: Vec<(String, String)>) -> Vec<(String, String)> {
+    let bundled = bundled_dependencies_of(manifest);
+    dependencies
+        .into_iter()
+        .filter(|(name, _)| !bundled.contains(name))
+        .collect()
+}
+
+fn specs_of(manifest: &Value, field: &str) -> Vec<(String, String)> {
+    manifest
+        .get(field)
+        .and_then(|d| d.as_object())
+        .map(|deps| {
+            deps.iter()
+                .filter_map(|(name, spec)| spec.as_str().map(|s| (name.clone(), s.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn is_optional(manifest: &Value, name: &str) -> bool {
+    manifest["optionalDependencies"].get(name).is_some()
+}
+
+/// An `os`, `cpu` or `libc` field as a list, for the lockfile.
+fn platform_field(manifest: &Value, field: &str) -> Vec<String> {
+    match manifest.get(field) {
+        Some(Value::String(entry)) => vec![entry.clone()],
+        Some(Value::Array(entries)) => entries.iter().filter_map(|e| e.as_str().map(String::from)).collect(),
+        _ => vec![],
+    }
+}
+
+/// The `peerDependencies` of a package manifest as `(name, range, optional)`,
+/// where optional peers are those marked in `peerDependenciesMeta`.
+pub fn peer_dependencies_of(manifest: &Value) -> Vec<(String, String, bool)> {
+    let Some(peers) = manifest.get("peerDependencies").and_then(|p| p.as_object()) else {
+        return vec![];
+    };
+
+    peers
+        .iter()
+        .filter_map(|(name, range)| {
+            let optional = manifest["peerDependenciesMeta"][name]["optional"].as_bool() == Some(true);
+            range.as_str().map(|range| (name.clone(), range.to_string(), optional))
+        })
+        .collect()
+}
+
+/// `dependencies` and `devDependencies` of a project or workspace manifest.
+pub fn all_dependencies_of(manifest: &Value) -> Vec<(String, String)> {
+    let mut dependencies = dependencies_of(manifest);
+    for (name, spec) in specs_of(manifest, "devDependencies") {
+        if !is_optional(manifest, &name) && !dependencies.iter().any(|(existing, _)| *existing == name) {
+            dependencies.push((name, spec));
+        }
+    }
+    dependencies
+}
+
+/// Splits `name@range` into its parts, keeping the leading `@` of scoped
+/// names. A missing range means `latest`.
+pub fn split_spec(spec: &str) -> (String, String) {
+    let search_from = usize::from(spec.starts_with('@'));
+    match spec[search_from..].find('@') {
+        Some(at) => {
+            let at = at + search_from;
+            (spec[..at].to_string(), spec[at + 1..].to_string())
+        }
+        None => (spec.to_string(), "latest".to_string()),
+    }
+}
+
+/// Reads the version of the package installed at `dir`, if any.
+pub fn installed_version(dir: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(dir.join("package.json")).ok()?;
+    let json: Value = serde_json::from_str(&content).ok()?;
+    json.get("version").and_then(|v| v.as_str()).map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A resolver that never touches the network, knowing only the
+    /// packuments of `packages`: `(name, [(version, dependencies)])`.
+    fn resolver(packages: &[(&str, &[(&str, Value)])]) -> Resolver {
+        let mut resolver = Resolver::new(net::client()).cache_mode(CacheMode::Offline);
+        for (name, versions) in packages {
+            let mut packument = json!({ "name": name, "dist-tags": {}, "versions": {} });
+            for (version, fields) in versions.iter() {
+                let mut manifest = json!({
+                    "name": name,
+                    "version": version,
+                    "dist": { "tarball": format!("https://registry.example/{}-{}.tgz", name, version) },
+                });
+                if let Some(fields) = fields.as_object() {
+                    for (key, value) in fields {
+                        manifest[key] = value.clone();
+                    }
+                }
+                packument["versions"][*version] = manifest;
+                packument["dist-tags"]["latest"] = json!(version);
+            }
+            resolver.packuments.insert(name.to_string(), packument);
+        }
+        resolver
+    }
+
+    fn member(name: &str, version: &str, dependencies: Value) -> Member {
+        Member {
+            name: name.to_string(),
+            version: version.to_string(),
+            path: PathBuf::from("packages").join(name),
+            manifest: json!({ "name": name, "version": version, "dependencies": dependencies }),
+        }
+    }
+
+    /// `(location, version)` of every placed package.
+    fn layout(tree: &Tree) -> Vec<(String, String)> {
+        let mut layout: Vec<(String, String)> = (1..tree.nodes.len())
+            .map(|i| (tree.location_key(i), tree.nodes[i].version.clone()))
+            .collect();
+        layout.sort();
+        layout
+    }
+
+    fn placed(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries.iter().map(|(location, version)| (location.to_string(), version.to_string())).collect()
+    }
+
+    fn deps(specs: &[(&str, &str)]) -> Vec<(String, String)> {
+        specs.iter().map(|(name, spec)| (name.to_string(), spec.to_string())).collect()
+    }
+
+    fn index_of(tree: &Tree, location: &str) -> usize {
+        (1..tree.nodes.len()).find(|&i| tree.location_key(i) == location).unwrap()
+    }
+
+    #[test]
+    fn installs_missing_peers_next_to_the_requester() {
+        let tree = resolver(&[
+            ("plugin", &[("1.0.0", json!({ "peerDependencies": { "host": "^1.0.0" } }))]),
+            ("host", &[("1.0.0", json!({})), ("2.0.0", json!({}))]),
+        ])
+        .resolve(&deps(&[("plugin", "^1.0.0")]))
+        .unwrap();
+        assert_eq!(layout(&tree), placed(&[("node_modules/host", "1.0.0"), ("node_modules/plugin", "1.0.0")]));
+        assert!(tree.peer_problems().is_empty());
+    }
+
+    #[test]
+    fn keeps_and_reports_a_conflicting_peer() {
+        let tree = resolver(&[
+            ("plugin", &[("1.0.0", json!({ "peerDependencies": { "host": "^1.0.0" } }))]),
+            ("host", &[("1.0.0", json!({})), ("2.0.0", json!({}))]),
+        ])
+        .resolve(&deps(&[("host", "^2.0.0"), ("plugin", "^1.0.0")]))
+        .unwrap();
+        assert_eq!(layout(&tree), placed(&[("node_modules/host", "2.0.0"), ("node_modules/plugin", "1.0.0")]));
+
+        let problems = tree.peer_problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].requester, index_of(&tree, "node_modules/plugin"));
+        assert_eq!((problems[0].name.as_str(), problems[0].range.as_str()), ("host", "^1.0.0"));
+        assert_eq!(problems[0].found, Some(index_of(&tree, "node_modules/host")));
+    }
+
+    #[test]
+    fn nests_a_version_conflicting_with_the_top_level() {
+        let tree = resolver(&[
+            ("a", &[("1.0.0", json!({})), ("2.0.0", json!({}))]),
+            ("b", &[("1.0.0", json!({ "dependencies": { "a": "^2.0.0" } }))]),
+        ])
+        .resolve(&deps(&[("a", "^1.0.0"), ("b", "^1.0.0")]))
+        .unwrap();
+        assert_eq!(
+            layout(&tree),
+            placed(&[
+                ("node_modules/a", "1.0.0"),
+                ("node_modules/b", "1.0.0"),
+                ("node_modules/b/node_modules/a", "2.0.0"),
+            ])
+        );
+    }
+
+    #[test]
+    fn legacy_peer_deps_leaves_peers_out() {
+        let tree = resolver(&[
+            ("plugin", &[("1.0.0", json!({ "peerDependencies": { "host": "^1.0.0" } }))]),
+            ("host", &[("1.0.0", json!({}))]),
+        ])
+        .legacy_peer_deps(true)
+        .resolve(&deps(&[("plugin", "^1.0.0")]))
+        .unwrap();
+        assert_eq!(layout(&tree), placed(&[("node_modules/plugin", "1.0.0")]));
+
+        let problems = tree.peer_problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].name.as_str(), problems[0].found), ("host", None));
+    }
+
+    #[test]
+    fn marks_packages_only_optional_packages_need_optional() {
+        let mut resolver = resolver(&[
+            ("keep", &[("1.0.0", json!({ "dependencies": { "shared": "^1.0.0" } }))]),
+            ("opt", &[("1.0.0", json!({ "dependencies": { "shared": "^1.0.0", "only": "^1.0.0" } }))]),
+            ("shared", &[("1.0.0", json!({}))]),
+            ("only", &[("1.0.0", json!({}))]),
+        ]);
+        let tree = resolver
+            .resolve_workspaces(&deps(&[("keep", "^1.0.0")]), &deps(&[("opt", "^1.0.0")]), &[])
+            .unwrap();
+        let optional = |location: &str| tree.nodes[index_of(&tree, location)].optional;
+        assert!(!optional("node_modules/keep"));
+        assert!(optional("node_modules/opt"));
+        assert!(!optional("node_modules/shared"));
+        assert!(optional("node_modules/only"));
+    }
+
+    #[test]
+    fn drops_a_locked_alias_pointed_at_another_package() {
+        let locked = LockFileEntry {
+            name: "pad".to_string(),
+            version: "npm:left-pad@^1".to_string(),
+            tarball_url: Some("https://registry.example/left-pad-1.3.0.tgz".to_string()),
+            resolved_version: "1.3.0".to_string(),
+            ..Default::default()
+        };
+        let resolve = |raw: &str| {
+            let mut resolver = resolver(&[("right-pad", &[("1.0.0", json!({}))])]);
+            resolver.locked.insert("node_modules/pad".to_string(), locked.clone());
+            resolver.resolve(&deps(&[("pad", raw)])).unwrap()
+        };
+
+        let tree = resolve("npm:left-pad@^1");
+        assert_eq!(layout(&tree), placed(&[("node_modules/pad", "1.3.0")]));
+        assert_eq!(tree.nodes[1].manifest["name"], "pad");
+
+        let tree = resolve("npm:right-pad@^1");
+        assert_eq!(layout(&tree), placed(&[("node_modules/pad", "1.0.0")]));
+        assert_eq!(tree.nodes[1].manifest["name"], "right-pad");
+    }
+
+    #[test]
+    fn links_workspace_siblings_for_plain_ranges() {
+        let members = [
+            member("a", "1.0.0", json!({ "b": "^1.0.0", "c": "*" })),
+            member("b", "1.2.0", json!({})),
+            member("c", "0.1.0", json!({})),
+        ];
+        let tree = resolver(&[]).resolve_workspaces(&[], &[], &members).unwrap();
+        assert_eq!(
+            layout(&tree),
+            placed(&[("node_modules/a", "1.0.0"), ("node_modules/b", "1.2.0"), ("node_modules/c", "0.1.0")])
+        );
+        assert!(tree.unresolved.is_empty());
+    }
+
+    #[test]
+    fn fetches_a_sibling_name_the_member_version_does_not_satisfy() {
+        let members = [member("a", "1.0.0", json!({ "b": "^2.0.0" })), member("b", "1.2.0", json!({}))];
+        let tree = resolver(&[("b", &[("2.0.0", json!({}))])])
+            .resolve_workspaces(&[], &[], &members)
+            .unwrap();
+        assert_eq!(
+            layout(&tree),
+            placed(&[
+                ("node_modules/a", "1.0.0"),
+                ("node_modules/a/node_modules/b", "2.0.0"),
+                ("node_modules/b", "1.2.0"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use serde_json::Value;
+use crate::logger;
+use crate::io::logger::colorize;
+use crate::runtime::task::{self, ScriptOptions};
+
+const PREFIX_COLORS: [&str; 8] = [
+    "cyan",
+    "magenta",
+    "yellow",
+    "green",
+    "blue",
+    "light_red",
+    "light_cyan",
+    "light_magenta",
+];
+
+/// A script to run in a specific package. `dependencies` are indices of
+/// earlier jobs that must succeed before this one starts.
+pub struct Job {
+    pub label: String,
+    pub package_dir: PathBuf,
+    pub json: Value,
+    pub task_name: String,
+    pub dependencies: Vec<usize>,
+}
+
+pub struct Settings {
+    pub parallel: bool,
+    pub continue_on_error: bool,
+    pub debug_mode: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Succeeded,
+    Failed,
+    Aborted,
+}
+
+/// Runs every job and reports a summary. In parallel mode a job starts as
+/// soon as its dependencies have succeeded; otherwise jobs run one at a time
+/// in order. Jobs whose dependencies failed are skipped, and unless
+/// `continue_on_error` is set the first failure stops the remaining jobs.
+/// Returns whether all succeeded.
+pub fn run_jobs(jobs: Vec<Job>, settings: &Settings) -> bool {
+    let width = jobs.iter().map(|job| job.label.len()).max().unwrap_or(0);
+    let abort = Arc::new(AtomicBool::new(false));
+    let prefixed = jobs.len() > 1;
+    let labels: Vec<String> = jobs.iter().map(|job| job.label.clone()).collect();
+    let dependencies: Vec<Vec<usize>> = jobs.iter().map(|job| job.dependencies.clone()).collect();
+
+    let mut pending: Vec<Option<(Job, ScriptOptions)>> = jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, job)| {
+            let prefix = prefixed.then(|| {
+                let color = PREFIX_COLORS[index % PREFIX_COLORS.len()];
+                colorize(color, &format!("[{:width$}]", job.label, width = width))
+            });
+            let options = ScriptOptions {
+                debug_mode: settings.debug_mode,
+                prefix,
+                abort: Some(Arc::clone(&abort)),
+            };
+            Some((job, options))
+        })
+        .collect();
+
+    let mut outcomes: Vec<Option<Outcome>> = vec![None; pending.len()];
+    let limit = if settings.parallel { usize::MAX } else { 1 };
+    let (sender, receiver) = mpsc::channel::<(usize, Outcome)>();
+    let mut running = 0;
+
+    loop {
+        for index in 0..pending.len() {
+            if pending[index].is_none() {
+                continue;
+            }
+            let blocked = dependencies[index]
+                .iter()
+                .any(|&dep| outcomes[dep].is_some_and(|outcome| outcome != Outcome::Succeeded));
+            if blocked || abort.load(Ordering::SeqCst) {
+                pending[index] = None;
+                outcomes[index] = Some(Outcome::Aborted);
+            }
+        }
+
+        for index in 0..pending.len() {
+            if running >= limit {
+                break;
+            }
+            let ready = dependencies[index]
+                .iter()
+                .all(|&dep| outcomes[dep] == Some(Outcome::Succeeded));
+            if !ready {
+                continue;
+            }
+            let Some((job, options)) = pending[index].take() else {
+                continue;
+            };
+
+            let sender = sender.clone();
+            let abort = Arc::clone(&abort);
+            let continue_on_error = settings.continue_on_error;
+            running += 1;
+            thread::spawn(move || {
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    run_job(&job, &options, &abort, continue_on_error)
+                }))
+                .unwrap_or_else(|_| {
+                    logger::error(&format!("Task '{}' panicked", job.label));
+                    Outcome::Failed
+                });
+                let _ = sender.send((index, outcome));
+            });
+        }
+
+        if running == 0 {
+            break;
+        }
+        let Ok((index, outcome)) = receiver.recv() else {
+            break;
+        };
+        outcomes[index] = Some(outcome);
+        running -= 1;
+    }
+
+    let outcomes: Vec<Outcome> = outcomes
+        .into_iter()
+        .map(|outcome| outcome.unwrap_or(Outcome::Aborted))
+        .collect();
+    let failed: Vec<&str> = outcomes
+        .iter()
+        .zip(&labels)
+        .filter(|(outcome, _)| **outcome == Outcome::Failed)
+        .map(|(_, label)| label.as_str())
+        .collect();
+    let aborted = outcomes
+        .iter()
+        .filter(|outcome| **outcome == Outcome::Aborted)
+        .count();
+
+    println!();
+    if failed.is_empty() && aborted == 0 {
+        logger::info(&format!("{} task(s) completed successfully.", outcomes.len()));
+        return true;
+    }
+
+    if !failed.is_empty() {
+        logger::error(&format!("Failed: {}", failed.join(", ")));
+    }
+    if aborted > 0 {
+        logger::warn(&format!("{} task(s) were stopped or skipped.", aborted));
+    }
+    false
+}
+
+fn run_job(job: &Job, options: &ScriptOptions, abort: &AtomicBool, continue_on_error: bool) -> Outcome {
+    if task::run_with_hooks(&job.package_dir, &job.json, &job.task_name, options) {
+        return Outcome::Succeeded;
+    }
+
+    if continue_on_error {
+        Outcome::Failed
+    } else if abort.swap(true, Ordering::SeqCst) {
+        Outcome::Aborted
+    } else {
+        Outcome::Failed
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::io::cache::{self, CacheMode};
+use crate::io::{gc, git, net};
+use crate::io::net::Client;
+use crate::utils::spec::Spec;
+use crate::utils::utils;
+
+/// A package fetched from somewhere other than the registry.
+pub struct Fetched {
+    /// Directory holding the package contents.
+    pub dir: PathBuf,
+    pub manifest: Value,
+    /// What the spec resolved to, recorded in the lockfile: the git URL
+    /// with the commit, the tarball URL or the path.
+    pub resolved: String,
+    /// Whether the package should be symlinked to `dir` rather than copied.
+    pub link: bool,
+    /// Integrity of the downloaded tarball, for tarball URLs.
+    pub integrity: Option<String>,
+}
+
+/// Fetches a git, tarball, file or link dependency. Relative paths are
+/// resolved against `base`, the directory of the package that declared the
+/// dependency. Git repositories and tarballs already in the cache are reused
+/// as `mode` allows, and tarballs are checked against `integrity` when the
+/// lockfile recorded one.
+pub fn fetch(
+    client: &Client,
+    spec: &Spec,
+    base: &Path,
+    mode: CacheMode,
+    integrity: Option<&str>,
+) -> Result<Fetched, Box<dyn std::error::Error>> {
+    let cache_dir = utils::get_cache_directory();
+
+    let (dir, resolved, link, integrity) = match spec {
+        Spec::Git { url, committish } => {
+            let (dir, commit) = git::checkout(url, committish.as_deref(), mode)?;
+            (dir, format!("git+{}#{}", url, commit), false, None)
+        }
+        Spec::Tarball { url } => {
+            let entry = cache_dir.join("_tarball").join(sanitize(url));
+            let cached = {
+                let _lock = cache::lock_entry(&entry, false)?;
+                cache::cached_tarball(&entry, integrity)
+            };
+            let (dir, integrity) = match cached {
+                Some(cached) => cached,
+                None if mode == CacheMode::Offline => return Err(CacheMode::offline_error(url).into()),
+                None => cache::add_tarball(client, &entry, url, integrity)?,
+            };
+            (dir, url.clone(), false, Some(integrity))
+        }
+        Spec::File { path } => {
+            let absolute = expand_path(base, path);
+            if absolute.is_dir() {
+                (absolute, format!("file:{}", path), true, None)
+            } else if absolute.is_file() {
+                (extract_file(&cache_dir, &absolute)?, format!("file:{}", path), false, None)
+            } else {
+                return Err(format!("No such file or directory: {}", absolute.display()).into());
+            }
+        }
+        Spec::Link { path } => (expand_path(base, path), format!("link:{}", path), true, None),
+        Spec::Registry { .. } | Spec::Workspace { .. } => {
+            return Err("Registry and workspace packages are resolved by the resolver".into())
+        }
+    };
+
+    if !link {
+        gc::record_use(&[&dir]);
+    }
+
+    let manifest_path = dir.join("package.json");
+    let manifest = if manifest_path.is_file() {
+        utils::read_json(&manifest_path)?
+    } else if link {
+        // Links may point at a directory that has no package.json yet.
+        Value::Object(Default::default())
+    } else {
+        return Err(format!("{} does not contain a package.json", resolved).into());
+    };
+
+    Ok(Fetched {
+        dir,
+        manifest,
+        resolved,
+        link,
+        integrity,
+    })
+}
+
+/// Extracts a package tarball on disk into the cache, keyed by the
+/// archive's hash so that it is only extracted again once it changes.
+fn extract_file(cache_dir: &Path, archive: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let contents = fs::read(archive)?;
+    let dir = cache_dir.join("_file").join(format!("{:x}", Sha256::digest(&contents)));
+    if dir.join("package.json").is_file() {
+        return Ok(dir);
+    }
+
+    let _lock = cache::lock_entry(&dir, true)?;
+    if !dir.join("package.json").is_file() {
+        cache::fill_entry(&dir, |temp| net::unpack_tarball(contents.as_slice(), &archive.to_string_lossy(), temp))?;
+    }
+    Ok(dir)
+}
+
+fn expand_path(base: &Path, path: &str) -> PathBuf {
+    let path = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().map(|home| home.join(rest)).unwrap_or_else(|| PathBuf::from(path)),
+        None => PathBuf::from(path),
+    };
+    let joined = base.join(path);
+    fs::canonicalize(&joined).unwrap_or(joined)
+}
+
+fn sanitize(source: &str) -> String {
+    source
+        .split("://")
+        .last()
+        .unwrap_or(source)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::sync::Once;
+
+    /// Points the cache at a scratch directory for the whole test run.
+    fn use_scratch_cache() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("snpm-test-cache-{}", std::process::id()));
+            std::env::set_var("SNPM_CACHE_DIR", dir);
+        });
+    }
+
+    fn run_git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=snpm", "-c", "user.email=snpm@example.com", "-c", "commit.gpgsign=false"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git is installed");
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// A bare repository whose history has one commit per version, each
+    /// tagged `v<version>`. Returns its `git+file://` URL and the commits.
+    fn bare_repo(name: &str, versions: &[&str]) -> (String, Vec<String>) {
+        let root = std::env::temp_dir().join(format!("snpm-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        let work = root.join("work");
+        fs::create_dir_all(&work).unwrap();
+        run_git(&work, &["init", "--quiet"]);
+
+        let mut commits = vec![];
+        for version in versions {
+            let manifest = format!(r#"{{"name":"{}","version":"{}"}}"#, name, version);
+            fs::write(work.join("package.json"), manifest).unwrap();
+            run_git(&work, &["add", "package.json"]);
+            run_git(&work, &["commit", "--quiet", "-m", version]);
+            run_git(&work, &["tag", &format!("v{}", version)]);
+            commits.push(run_git(&work, &["rev-parse", "HEAD"]));
+        }
+        run_git(&root, &["clone", "--quiet", "--bare", "work", "repo.git"]);
+
+        (format!("git+file://{}", root.join("repo.git").display()), commits)
+    }
+
+    fn fetch_git(name: &str, raw: &str) -> Fetched {
+        use_scratch_cache();
+        let spec = Spec::parse(name, raw);
+        assert!(matches!(spec, Spec::Git { .. }), "{} is not a git spec", raw);
+        fetch(&net::client(), &spec, Path::new("."), CacheMode::Online, None).unwrap()
+    }
+
+    #[test]
+    fn checks_out_a_tag() {
+        let (url, commits) = bare_repo("git-tag", &["1.0.0", "1.1.0"]);
+        let fetched = fetch_git("git-tag", &format!("{}#v1.0.0", url));
+        assert_eq!(fetched.manifest["version"], "1.0.0");
+        assert_eq!(fetched.resolved, format!("{}#{}", url, commits[0]));
+        assert!(!fetched.link);
+    }
+
+    #[test]
+    fn picks_the_highest_tag_in_a_semver_range() {
+        let (url, commits) = bare_repo("git-semver", &["1.0.0", "1.2.0", "2.0.0"]);
+        let fetched = fetch_git("git-semver", &format!("{}#semver:^1", url));
+        assert_eq!(fetched.manifest["version"], "1.2.0");
+        assert_eq!(fetched.resolved, format!("{}#{}", url, commits[1]));
+    }
+
+    #[test]
+    fn checks_out_a_commit() {
+        let (url, commits) = bare_repo("git-commit", &["1.0.0", "2.0.0"]);
+        let fetched = fetch_git("git-commit", &format!("{}#{}", url, commits[0]));
+        assert_eq!(fetched.manifest["version"], "1.0.0");
+
+        let fetched = fetch_git("git-commit", &url);
+        assert_eq!(fetched.manifest["version"], "2.0.0");
+        assert_eq!(fetched.resolved, format!("{}#{}", url, commits[1]));
+    }
+}
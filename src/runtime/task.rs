@@ -1,49 +1,35 @@
-use std::{env, fs};
+use std::collections::HashSet;
+use std::{env, fs, thread};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use serde_json::Value;
 use crate::logger;
 use crate::utils::utils::ASCII_ART;
 use crate::io::logger::colorize;
 use crate::utils::utils::get_framework_info;
-use std::process::Command;
-
-fn find_binary_recursive(dir: &Path, name: &str) -> Option<PathBuf> {
-    if !dir.exists() || !dir.is_dir() {
-        return None;
-    }
+use crate::runtime::{lifecycle, runner};
+use crate::utils::{utils, workspace};
+use std::process::{Child, Command, Stdio};
+
+/// Per-invocation settings for running scripts. `prefix` captures and labels
+/// the child's output, and `abort` lets a sibling task stop this one early.
+#[derive(Clone, Default)]
+pub struct ScriptOptions {
+    pub debug_mode: bool,
+    pub prefix: Option<String>,
+    pub abort: Option<Arc<AtomicBool>>,
+}
 
-    #[cfg(windows)]
-    let possible_extensions = vec![".cmd", ".exe", ".bat", ""];
-    #[cfg(not(windows))]
-    let possible_extensions = vec!["", ".sh"];
-    
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            
-            if path.is_file() {
-                if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                    for ext in &possible_extensions {
-                        let target_name = if ext.is_empty() {
-                            name.to_string()
-                        } else {
-                            format!("{}{}", name, ext)
-                        };
-                        if file_name == target_name {
-                            return Some(path);
-                        }
-                    }
-                }
-            }
-            
-            if path.is_dir() && path.file_name().and_then(|n| n.to_str()) != Some("node_modules") {
-                if let Some(found) = find_binary_recursive(&path, name) {
-                    return Some(found);
-                }
-            }
+impl ScriptOptions {
+    fn print(&self, message: &str) {
+        match &self.prefix {
+            Some(prefix) => println!("{} {}", prefix, message),
+            None => println!("{}", message),
         }
     }
-    None
 }
 
 pub fn run(task_name: &str) {
@@ -58,329 +44,521 @@ pub fn run(task_name: &str) {
     let content = fs::read_to_string(package_file).expect("Failed to read package.json");
     let json: Value = serde_json::from_str(&content).expect("Failed to parse package.json");
 
-    let package_name = json
-        .get("name")
-        .and_then(|n| n.as_str())
-        .unwrap_or("unknown");
-    let package_version = json
-        .get("version")
-        .and_then(|v| v.as_str())
-        .unwrap_or("0.0.0");
-
     let scripts = json.get("scripts").and_then(|s| s.as_object());
 
     match scripts {
         None => {
-            let parts: Vec<&str> = task_name.split_whitespace().collect();
-            if parts.is_empty() {
-                logger::error("Empty command");
-                return;
-            }
-
-            match std::process::Command::new(&parts[0])
-                .args(&parts[1..])
-                .status() {
-                Ok(status) => {
-                    if !status.success() {
-                        logger::error(&format!(
-                            "Command '{}' failed with exit code: {}",
-                            task_name,
-                            status.code().unwrap_or(-1)
-                        ));
-                    }
-                }
-                Err(e) => logger::error(&format!("Failed to execute command: {}", e)),
-            }
-            return;
+            logger::error("No scripts found in package.json");
+            logger::info(&format!("To run a command directly, use `snpm exec {}`", task_name));
+            std::process::exit(1);
         }
         Some(scripts_obj) => {
             if let Some(script) = scripts_obj.get(task_name) {
-                if let Some(command) = script.as_str() {
-                    println!("{}", colorize("red", ASCII_ART));
-                    println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
-
-                    println!("  > Starting snpm tasks...");
-                    println!("  > {}@{} {}", package_name, package_version, task_name);
-                    println!("  > {}", command);
-
-                    if let Some((framework_name, color)) = get_framework_info(command) {
-                        println!("  > Booting {}...", colorize(color, framework_name));
-                    }
-                    println!();
-
-                    let parts: Vec<&str> = command.split_whitespace().collect();
-                    if parts.is_empty() {
-                        logger::error("Empty command");
-                        return;
-                    }
-
-                    let binary_name = parts[0];
-                    
-                    if binary_name == "echo" || binary_name == "cd" || binary_name == "pwd" {
-                        let status = Command::new("sh")
-                            .arg("-c")
-                            .arg(command)
-                            .status();
-
-                        if let Err(e) = status {
-                            logger::error(&format!("Failed to execute command: {}", e));
-                        }
-                        return;
-                    }
-
-                    let current_dir = env::current_dir().expect("Failed to get current directory");
-                    let node_modules = current_dir.join("node_modules");
-                    let binary_path = node_modules.join(".bin").join(binary_name);
-
-                    let command_result = if binary_path.exists() {
-                        Command::new(binary_path)
-                            .args(&parts[1..])
-                            .status()
-                    } else {
-                        Command::new(binary_name)
-                            .args(&parts[1..])
-                            .status()
-                    };
-
-                    if let Err(e) = command_result {
-                        logger::error(&format!("Failed to execute command: {}", e));
-                    }
-
-                    let mut possible_paths = vec![];
-                    
-                    possible_paths.push(node_modules.join(".bin").join(binary_name));
-                    
-                    if let Ok(system_path) = which::which(binary_name) {
-                        possible_paths.push(system_path);
-                    }
-
-                    let executable_path = possible_paths.iter().find(|path| path.exists());
-
-                    match executable_path {
-                        Some(path) => {
-                            let mut command = Command::new(path);
-                            command.args(&parts[1..]);
-                            
-                            if let Err(e) = command.status() {
-                                logger::error(&format!("Failed to execute command: {}", e));
-                            }
-                        }
-                        None => {
-                            let mut command = Command::new(binary_name);
-                            command.args(&parts[1..]);
-                            
-                            match command.status() {
-                                Ok(_) => (),
-                                Err(e) => logger::error(&format!("Command not found in node_modules or system: {}", e))
-                            }
-                        }
-                    }
-
-                    if debug_mode {
-                        logger::info(&format!("Looking for binary '{}' in:", binary_name));
-                        for path in &possible_paths {
-                            logger::info(&format!("  - {}", path.display()));
-                        }
-                    }
-
-                    let binary_path = match possible_paths.iter().find(|p| p.exists()) {
-                        Some(path) => path.to_path_buf(),
-                        None => {
-                            if let Some(found_path) = find_binary_recursive(&node_modules, binary_name) {
-                                if debug_mode {
-                                    logger::info(&format!("Found binary through recursive search at: {}", found_path.display()));
-                                }
-                                found_path
-                            } else {
-                                logger::error(&format!(
-                                    "Binary '{}' not found. Please make sure the package is installed.",
-                                    binary_name
-                                ));
-                                return;
-                            }
-                        }
-                    };
-
-                    if debug_mode {
-                        logger::info(&format!("Using binary at: {}", binary_path.display()));
-                    }
-
-                    let result = std::process::Command::new(&binary_path)
-                        .args(&parts[1..])
-                        .current_dir(&current_dir)
-                        .status();
-
-                    match result {
-                        Ok(status) => {
-                            if !status.success() {
-                                logger::error(&format!(
-                                    "Script '{}' failed with exit code: {}",
-                                    task_name,
-                                    status.code().unwrap_or(-1)
-                                ));
-                            }
-                        }
-                        Err(e) => {
-                            #[cfg(windows)]
-                            let invalid_executable_error = Some(193); 
-                            
-                            #[cfg(not(windows))]
-                            let invalid_executable_error = Some(8);
-                            
-                            if e.raw_os_error() == invalid_executable_error {
-                                if debug_mode {
-                                    logger::info("Direct execution failed, trying through node...");
-                                }
-                                
-                                let node_result = std::process::Command::new("node")
-                                    .arg(&binary_path)
-                                    .args(&parts[1..])
-                                    .current_dir(&current_dir)
-                                    .status();
-
-                                match node_result {
-                                    Ok(status) => {
-                                        if !status.success() {
-                                            logger::error(&format!(
-                                                "Script '{}' failed with exit code: {}",
-                                                task_name,
-                                                status.code().unwrap_or(-1)
-                                            ));
-                                        }
-                                    }
-                                    Err(e) => logger::error(&format!("Failed to execute script through node: {}", e)),
-                                }
-                            } else {
-                                logger::error(&format!("Failed to execute script: {}", e));
-                            }
-                        }
-                    }
-                } else {
+                if script.as_str().is_none() {
                     logger::error(&format!("Script '{}' is not a string", task_name));
-                }
-            } else {
-                println!("Script '{}' not found in package.json, attempting to run as system command...", task_name);
-                let parts: Vec<&str> = task_name.split_whitespace().collect();
-                if parts.is_empty() {
-                    logger::error("Empty command");
                     return;
                 }
 
-                match std::process::Command::new(&parts[0])
-                    .args(&parts[1..])
-                    .status() {
-                    Ok(status) => {
-                        if !status.success() {
-                            logger::error(&format!(
-                                "Command '{}' failed with exit code: {}",
-                                task_name,
-                                status.code().unwrap_or(-1)
-                            ));
-                        }
-                    }
-                    Err(e) => logger::error(&format!("Failed to execute command: {}", e)),
+                println!("{}", colorize("red", ASCII_ART));
+                println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
+                println!("  > Starting snpm tasks...");
+
+                let current_dir = env::current_dir().expect("Failed to get current directory");
+                let options = ScriptOptions {
+                    debug_mode,
+                    ..Default::default()
+                };
+
+                if !run_with_hooks(&current_dir, &json, task_name, &options) {
+                    std::process::exit(1);
                 }
+            } else {
+                report_unknown_script(task_name, scripts_obj);
+                std::process::exit(1);
             }
         }
     }
 }
 
-pub fn run_npx(package_args: &str) {
+/// Prints every script in package.json, plus those of each workspace member.
+pub fn list(json_output: bool) {
+    let package_file = Path::new("package.json");
+
+    if !package_file.exists() {
+        logger::error("No package.json file found in the current directory.");
+        return;
+    }
+
+    let json = match utils::read_json(package_file) {
+        Ok(json) => json,
+        Err(e) => {
+            logger::error(&e.to_string());
+            return;
+        }
+    };
+
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let mut packages = vec![(PathBuf::from("."), json.clone())];
+
+    for member in workspace::find_members(&current_dir, &json) {
+        match utils::read_json(&member.join("package.json")) {
+            Ok(member_json) => {
+                let relative = member.strip_prefix(&current_dir).unwrap_or(&member).to_path_buf();
+                packages.push((relative, member_json));
+            }
+            Err(e) => logger::warn(&e.to_string()),
+        }
+    }
+
+    if json_output {
+        let entries: Vec<Value> = packages
+            .iter()
+            .map(|(path, package_json)| {
+                serde_json::json!({
+                    "name": package_json.get("name"),
+                    "version": package_json.get("version"),
+                    "path": path.to_string_lossy().replace('\\', "/"),
+                    "scripts": package_json.get("scripts").cloned().unwrap_or_else(|| serde_json::json!({})),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap_or_default());
+        return;
+    }
+
+    for (index, (path, package_json)) in packages.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        let package_name = package_json
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or("unknown");
+        let package_version = package_json
+            .get("version")
+            .and_then(|v| v.as_str())
+            .unwrap_or("0.0.0");
+
+        println!(
+            "Scripts available in {}@{} {}",
+            colorize("magenta", package_name),
+            package_version,
+            colorize("gray", &format!("({})", path.display()))
+        );
+
+        match package_json.get("scripts").and_then(|s| s.as_object()) {
+            Some(scripts_obj) if !scripts_obj.is_empty() => {
+                for (name, command) in scripts_obj {
+                    println!("  {}", colorize("cyan", name));
+                    println!("    {}", command.as_str().unwrap_or("<not a string>"));
+                }
+            }
+            _ => println!("  {}", colorize("gray", "(no scripts)")),
+        }
+    }
+}
+
+/// Runs several scripts, given as names or globs like `build:*`, either one
+/// after another or all at once.
+pub fn run_many(patterns: &[String], parallel: bool, continue_on_error: bool) {
     let debug_mode = env::args().any(|arg| arg == "--debug");
+    let package_file = Path::new("package.json");
+
+    if !package_file.exists() {
+        logger::error("No package.json file found in the current directory.");
+        return;
+    }
+
+    let content = fs::read_to_string(package_file).expect("Failed to read package.json");
+    let json: Value = serde_json::from_str(&content).expect("Failed to parse package.json");
+
+    let Some(scripts_obj) = json.get("scripts").and_then(|s| s.as_object()) else {
+        logger::error("No scripts found in package.json");
+        return;
+    };
+
+    let mut task_names: Vec<String> = vec![];
+    for pattern in patterns {
+        let matched: Vec<&String> = scripts_obj
+            .keys()
+            .filter(|name| utils::matches_task_pattern(pattern, name))
+            .collect();
+
+        if matched.is_empty() {
+            if pattern.contains('*') {
+                logger::error(&format!("No scripts in package.json match '{}'", pattern));
+            } else {
+                report_unknown_script(pattern, scripts_obj);
+            }
+            std::process::exit(1);
+        }
+
+        for name in matched {
+            if !task_names.contains(name) {
+                task_names.push(name.clone());
+            }
+        }
+    }
+
+    println!("{}", colorize("red", ASCII_ART));
     println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
-    println!("  > npx {}\n", package_args);
+    println!(
+        "  > Starting snpm tasks {}: {}\n",
+        if parallel { "in parallel" } else { "in sequence" },
+        task_names.join(", ")
+    );
 
     let current_dir = env::current_dir().expect("Failed to get current directory");
-    
-    #[cfg(windows)]
-    let npx_command = {
-        let node_path = std::process::Command::new("where")
-            .arg("node")
-            .output()
-            .ok()
-            .and_then(|output| String::from_utf8(output.stdout).ok())
-            .and_then(|s| s.lines().next().map(|s| s.to_string()));
-
-        match node_path {
-            Some(path) => {
-                let node_dir = Path::new(&path).parent().unwrap_or(Path::new(""));
-                node_dir.join("npx.cmd")
+    let jobs = task_names
+        .iter()
+        .map(|name| runner::Job {
+            label: name.clone(),
+            package_dir: current_dir.clone(),
+            json: json.clone(),
+            task_name: name.clone(),
+            dependencies: vec![],
+        })
+        .collect();
+
+    let settings = runner::Settings {
+        parallel,
+        continue_on_error,
+        debug_mode,
+    };
+
+    if !runner::run_jobs(jobs, &settings) {
+        std::process::exit(1);
+    }
+}
+
+/// Runs the scripts matching `patterns` in every selected workspace
+/// package. All members are selected unless `filters` narrow them down (see
+/// `workspace::filter_members`). Packages run after the workspace packages
+/// they depend on; members without a matching script are skipped.
+pub fn run_workspaces(patterns: &[String], filters: &[String], parallel: bool, continue_on_error: bool) {
+    let debug_mode = env::args().any(|arg| arg == "--debug");
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let root = workspace::find_root(&current_dir).unwrap_or(current_dir);
+
+    let json = match utils::read_json(&root.join("package.json")) {
+        Ok(json) => json,
+        Err(e) => {
+            logger::error(&e.to_string());
+            std::process::exit(1);
+        }
+    };
+
+    let members = workspace::load_members(&root, &json);
+    if members.is_empty() {
+        logger::error("No workspace packages found. Add a 'workspaces' field to the root package.json.");
+        std::process::exit(1);
+    }
+
+    let selected = if filters.is_empty() {
+        (0..members.len()).collect()
+    } else {
+        match workspace::filter_members(&root, &members, filters) {
+            Ok(selected) => selected,
+            Err(e) => {
+                logger::error(&format!("Failed to apply filter: {}", e));
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let graph = workspace::dependency_graph(&members);
+    let order = workspace::topological_order(&graph, &selected);
+    let dependencies: Vec<Vec<usize>> = (0..members.len())
+        .map(|index| workspace::dependencies_of(&graph, index))
+        .collect();
+
+    // (member, task) for every job, in topological order.
+    let mut planned: Vec<(usize, String)> = vec![];
+    for &member_index in &order {
+        let member = &members[member_index];
+        let scripts: Vec<&String> = member
+            .manifest
+            .get("scripts")
+            .and_then(|s| s.as_object())
+            .map(|scripts| scripts.keys().collect())
+            .unwrap_or_default();
+
+        let mut matched = false;
+        for pattern in patterns {
+            for name in scripts.iter().filter(|name| utils::matches_task_pattern(pattern, name)) {
+                if !planned.contains(&(member_index, name.to_string())) {
+                    planned.push((member_index, name.to_string()));
+                    matched = true;
+                }
             }
-            None => PathBuf::from("npx.cmd")
         }
+        if !matched && debug_mode {
+            logger::info(&format!("Skipping {}: no matching script", member.name));
+        }
+    }
+
+    if planned.is_empty() {
+        logger::error(&format!(
+            "No selected workspace package has a script matching {}",
+            patterns.join(", ")
+        ));
+        std::process::exit(1);
+    }
+
+    let single_task = planned.iter().all(|(_, task_name)| *task_name == planned[0].1);
+    let jobs: Vec<runner::Job> = planned
+        .iter()
+        .enumerate()
+        .map(|(index, (member_index, task_name))| {
+            let member = &members[*member_index];
+
+            // A task waits for the same task in the packages this one
+            // depends on, even indirectly, and for the package's own
+            // earlier tasks.
+            let dependencies = planned[..index]
+                .iter()
+                .enumerate()
+                .filter(|(_, (other, other_task))| {
+                    (dependencies[*member_index].contains(other) && other_task == task_name) || other == member_index
+                })
+                .map(|(dep_index, _)| dep_index)
+                .collect();
+
+            runner::Job {
+                label: if single_task {
+                    member.name.clone()
+                } else {
+                    format!("{} {}", member.name, task_name)
+                },
+                package_dir: root.join(&member.path),
+                json: member.manifest.clone(),
+                task_name: task_name.clone(),
+                dependencies,
+            }
+        })
+        .collect();
+
+    println!("{}", colorize("red", ASCII_ART));
+    println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
+    println!(
+        "  > Running {} in {} workspace package(s) {}\n",
+        patterns.join(", "),
+        jobs.iter().map(|job| &job.package_dir).collect::<HashSet<_>>().len(),
+        if parallel { "in parallel" } else { "in dependency order" }
+    );
+
+    let settings = runner::Settings {
+        parallel,
+        continue_on_error,
+        debug_mode,
+    };
+
+    if !runner::run_jobs(jobs, &settings) {
+        std::process::exit(1);
+    }
+}
+
+/// Runs `pre<task>`, `<task>` and `post<task>` in order, stopping at the
+/// first failure.
+pub fn run_with_hooks(package_dir: &Path, json: &Value, task_name: &str, options: &ScriptOptions) -> bool {
+    let Some(scripts_obj) = json.get("scripts").and_then(|s| s.as_object()) else {
+        return false;
     };
 
-    #[cfg(not(windows))]
-    let npx_command = PathBuf::from("npx");
+    let package_name = json
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or("unknown");
+    let package_version = json
+        .get("version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("0.0.0");
+
+    let events = [
+        format!("pre{}", task_name),
+        task_name.to_string(),
+        format!("post{}", task_name),
+    ];
+
+    for event in &events {
+        let Some(command) = scripts_obj.get(event).and_then(|s| s.as_str()) else {
+            continue;
+        };
+
+        options.print(&format!("  > {}@{} {}", package_name, package_version, event));
+        options.print(&format!("  > {}", command));
+
+        if let Some((framework_name, color)) = get_framework_info(command) {
+            options.print(&format!("  > Booting {}...", colorize(color, framework_name)));
+        }
+        if options.prefix.is_none() {
+            println!();
+        }
+
+        if !run_script(package_dir, json, event, command, options) {
+            return false;
+        }
+    }
 
-    if debug_mode {
-        logger::info(&format!("Using npx at: {}", npx_command.display()));
+    true
+}
+
+fn report_unknown_script(task_name: &str, scripts_obj: &serde_json::Map<String, Value>) {
+    logger::error(&format!("Script '{}' not found in package.json", task_name));
+
+    let threshold = (task_name.len() / 3).max(2);
+    let mut suggestions: Vec<(usize, &String)> = scripts_obj
+        .keys()
+        .map(|name| (utils::edit_distance(task_name, name), name))
+        .filter(|(distance, _)| *distance <= threshold)
+        .collect();
+    suggestions.sort();
+
+    if !suggestions.is_empty() {
+        let names: Vec<&str> = suggestions
+            .iter()
+            .take(3)
+            .map(|(_, name)| name.as_str())
+            .collect();
+        logger::info(&format!("Did you mean: {}?", names.join(", ")));
     }
-    
-    match std::process::Command::new(npx_command)
-        .args(package_args.split_whitespace())
-        .current_dir(current_dir)
+    logger::info("Run `snpm task` to list available scripts, or `snpm exec <command>` to run a command directly.");
+}
+
+/// Runs an arbitrary command with the package's `node_modules/.bin` on
+/// `PATH` and the npm script environment applied, exiting with its code.
+pub fn exec(command_args: &[String]) {
+    let Some((program, program_args)) = command_args.split_first() else {
+        logger::error("No command provided for exec");
+        return;
+    };
+
+    let current_dir = env::current_dir().expect("Failed to get current directory");
+    let json = utils::read_json(&current_dir.join("package.json")).unwrap_or(Value::Null);
+    let command_line = command_args.join(" ");
+    let environment = lifecycle::environment(&current_dir, &json, "exec", &command_line);
+
+    let program = environment
+        .iter()
+        .find(|(key, _)| key == "PATH")
+        .and_then(|(_, path)| which::which_in(program, Some(path), &current_dir).ok())
+        .unwrap_or_else(|| PathBuf::from(program));
+
+    match Command::new(&program)
+        .args(program_args)
+        .current_dir(&current_dir)
+        .envs(environment)
         .status()
     {
         Ok(status) => {
             if !status.success() {
                 logger::error(&format!(
-                    "npx execution failed with exit code: {}",
+                    "Command '{}' failed with exit code: {}",
+                    command_line,
                     status.code().unwrap_or(-1)
                 ));
+                std::process::exit(status.code().unwrap_or(1));
             }
         }
-        Err(e) => logger::error(&format!("Failed to execute npx: {}", e)),
+        Err(e) => {
+            logger::error(&format!("Failed to execute command: {}", e));
+            std::process::exit(1);
+        }
     }
 }
 
-pub fn run_create(create_args: &str) {
-    let debug_mode = env::args().any(|arg| arg == "--debug");
-    println!("SuperNPM v{}\n", env!("CARGO_PKG_VERSION"));
-    println!("  > npm create {}\n", create_args);
+/// Runs a single script command from `package_dir` with the npm lifecycle
+/// environment applied. Like npm, the command goes through `sh -c` (`cmd /C`
+/// on Windows), so operators, variables and redirections work, and the
+/// package's `node_modules/.bin` directories are on `PATH`. Returns whether
+/// it exited successfully.
+fn run_script(package_dir: &Path, json: &Value, event: &str, command: &str, options: &ScriptOptions) -> bool {
+    if command.trim().is_empty() {
+        logger::error("Empty command");
+        return false;
+    }
 
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-    
-    // On Windows, we need to use the full path to npm.cmd
-    #[cfg(windows)]
-    let npm_command = {
-        let node_path = std::process::Command::new("where")
-            .arg("node")
-            .output()
-            .ok()
-            .and_then(|output| String::from_utf8(output.stdout).ok())
-            .and_then(|s| s.lines().next().map(|s| s.to_string()));
-
-        match node_path {
-            Some(path) => {
-                let node_dir = Path::new(&path).parent().unwrap_or(Path::new(""));
-                node_dir.join("npm.cmd")
+    let environment = lifecycle::environment(package_dir, json, event, command);
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    if options.debug_mode {
+        logger::info(&format!("Running through {} {}: {}", shell, flag, command));
+    }
+
+    let mut script = Command::new(shell);
+    script
+        .arg(flag)
+        .arg(command)
+        .current_dir(package_dir)
+        .envs(environment);
+
+    match spawn(&mut script, options) {
+        Ok(child) => wait(child, event, options),
+        Err(e) => {
+            logger::error(&format!("Failed to execute script: {}", e));
+            false
+        }
+    }
+}
+
+fn spawn(command: &mut Command, options: &ScriptOptions) -> std::io::Result<Child> {
+    if options.prefix.is_some() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    command.spawn()
+}
+
+fn wait(mut child: Child, event: &str, options: &ScriptOptions) -> bool {
+    let mut readers = vec![];
+    if let Some(prefix) = &options.prefix {
+        if let Some(stdout) = child.stdout.take() {
+            let prefix = prefix.clone();
+            readers.push(thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    println!("{} {}", prefix, line);
+                }
+            }));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let prefix = prefix.clone();
+            readers.push(thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    eprintln!("{} {}", prefix, line);
+                }
+            }));
+        }
+    }
+
+    let status = loop {
+        if options.abort.as_ref().is_some_and(|abort| abort.load(Ordering::SeqCst)) {
+            let _ = child.kill();
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => {
+                logger::error(&format!("Failed to wait for script: {}", e));
+                break None;
             }
-            None => PathBuf::from("npm.cmd") // Fallback to just npm.cmd
         }
     };
 
-    #[cfg(not(windows))]
-    let npm_command = PathBuf::from("npm");
-
-    if debug_mode {
-        logger::info(&format!("Using npm at: {}", npm_command.display()));
+    for reader in readers {
+        let _ = reader.join();
     }
-    
-    match std::process::Command::new(npm_command)
-        .arg("create")
-        .args(create_args.split_whitespace())
-        .current_dir(current_dir)
-        .status()
-    {
-        Ok(status) => {
-            if !status.success() {
+
+    match status {
+        Some(status) if status.success() => true,
+        Some(status) => {
+            if !options.abort.as_ref().is_some_and(|abort| abort.load(Ordering::SeqCst)) {
                 logger::error(&format!(
-                    "npm create failed with exit code: {}",
+                    "Script '{}' failed with exit code: {}",
+                    event,
                     status.code().unwrap_or(-1)
                 ));
             }
+            false
         }
-        Err(e) => logger::error(&format!("Failed to execute npm create: {}", e)),
+        None => false,
     }
 }
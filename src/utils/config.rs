@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{env, fs};
+
+pub const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org/";
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Loads `~/.npmrc`, then `./.npmrc`, then `npm_config_*` environment
+    /// variables, with later sources taking precedence.
+    pub fn load() -> Self {
+        let mut config = Config::default();
+
+        if let Some(home) = dirs::home_dir() {
+            config.merge_file(&home.join(".npmrc"));
+        }
+        if let Ok(current_dir) = env::current_dir() {
+            config.merge_file(&current_dir.join(".npmrc"));
+        }
+
+        for (key, value) in env::vars() {
+            if let Some(name) = key.strip_prefix("npm_config_") {
+                config.set(&name.replace('_', "-"), &value);
+            }
+        }
+
+        config
+    }
+
+    fn merge_file(&mut self, path: &Path) {
+        let Ok(content) = fs::read_to_string(path) else {
+            return;
+        };
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim().trim_matches('"');
+                self.set(key.trim(), &expand_env(value));
+            }
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_lowercase(), value.to_string());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    /// Whether a boolean setting such as `legacy-peer-deps` is turned on.
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.get(key).is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    pub fn registry(&self) -> String {
+        let registry = self.get("registry").unwrap_or(DEFAULT_REGISTRY);
+        format!("{}/", registry.trim_end_matches('/'))
+    }
+
+    /// The `//host/path/:_authToken` entry that applies to `registry`.
+    pub fn auth_token(&self, registry: &str) -> Option<&str> {
+        let without_scheme = registry.split_once("//").map(|(_, rest)| rest).unwrap_or(registry);
+        self.get(&format!("//{}:_authtoken", without_scheme))
+    }
+
+    /// Entries that npm would export to scripts as `npm_config_*`; auth
+    /// and registry-scoped keys are never exported.
+    pub fn exported(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values
+            .iter()
+            .filter(|(key, _)| !key.starts_with("//") && !key.starts_with('_'))
+    }
+}
+
+fn expand_env(value: &str) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        match rest[start..].find('}') {
+            Some(end) => {
+                let name = &rest[start + 2..start + end];
+                result.push_str(&env::var(name).unwrap_or_default());
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
@@ -7,8 +7,37 @@ pub fn version() {
 
     println!("{}", colorize("white", "Usage: snpm <command> [options]"));
     println!("{}", colorize("white", "Commands:"));
-    println!("{}", colorize("white", "  install       Install dependencies"));
-    println!("{}", colorize("white", "  task          Run a task"));
-    println!("{}", colorize("white", "  x | exec      Execute a package"));
+    println!("{}", colorize("white", "  install       Install dependencies, including every workspace"));
+    println!("{}", colorize("white", "                  --force, -f              Use the latest version when no version matches"));
+    println!("{}", colorize("white", "                  --legacy-peer-deps       Don't install or check peer dependencies"));
+    println!("{}", colorize("white", "                  --strict-peer-deps       Fail when peer dependencies are unmet"));
+    println!("{}", colorize("white", "                  --engine-strict          Fail when engines.node doesn't match"));
+    println!("{}", colorize("white", "                  --offline                Install from the cache without network access"));
+    println!("{}", colorize("white", "                  --prefer-offline         Use cached metadata and packages without revalidating"));
+    println!("{}", colorize("white", "                  --os, --cpu, --libc      Install optional packages for another platform"));
+    println!("{}", colorize("white", "  task          Run a task, or list scripts when no name is given"));
+    println!("{}", colorize("white", "                  --parallel, -p           Run several tasks at once"));
+    println!("{}", colorize("white", "                  --sequential, -s         Run several tasks in order"));
+    println!("{}", colorize("white", "                  --continue-on-error, -c  Keep going after a task fails"));
+    println!("{}", colorize("white", "                  --workspaces, -ws        Run in every workspace package"));
+    println!("{}", colorize("white", "                  --filter <pattern>       Run in matching workspace packages"));
+    println!("{}", colorize("white", "                  --json                   List scripts as JSON"));
+    println!("{}", colorize("white", "  x             Execute a package"));
+    println!("{}", colorize("white", "  exec          Run a command with node_modules/.bin on PATH"));
     println!("{}", colorize("white", "  create        Create a new project"));
+    println!("{}", colorize("white", "  init          Create a package.json, or run an initializer"));
+    println!("{}", colorize("white", "                  --yes, -y                Accept all defaults"));
+    println!("{}", colorize("white", "  pack          Create a tarball of the current package"));
+    println!("{}", colorize("white", "  publish       Publish the current package to the registry"));
+    println!("{}", colorize("white", "                  --tag <tag>              Dist-tag to publish under"));
+    println!("{}", colorize("white", "                  --dry-run                Show what would be published"));
+    println!("{}", colorize("white", "  cache         Manage the package cache"));
+    println!("{}", colorize("white", "                  ls [<name>]              List cached packages"));
+    println!("{}", colorize("white", "                  verify                   Check cached tarballs and remove corrupt ones"));
+    println!("{}", colorize("white", "                  clean [<name>[@range]]   Remove packages, or the whole cache"));
+    println!("{}", colorize("white", "                  add <spec>               Download a package into the cache"));
+    println!("{}", colorize("white", "                  dir                      Print the cache location"));
+    println!("{}", colorize("white", "                  prune                    Remove least recently used packages"));
+    println!("{}", colorize("white", "                    --max-size <size>      Size to shrink the cache to, such as 2GB"));
+    println!("{}", colorize("white", "                    --max-unused-days <n>  Remove packages unused for longer"));
 }
@@ -1,70 +1,106 @@
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LockFileEntry {
-    name: String,
-    version: String,
-    tarball_url: Option<String>,
-    use_npm_fallback: bool,
-    resolved_version: String,
+    pub name: String,
+    pub version: String,
+    pub tarball_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    pub use_npm_fallback: bool,
+    pub resolved_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub optional_dependencies: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_dependencies: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub optional_peers: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overridden_by: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub engines: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpu: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libc: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
 }
 
+/// `snpm.lockd`, keyed by install location such as `node_modules/a` or
+/// `node_modules/a/node_modules/b`, covering the root and every workspace.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LockFile {
     version: String,
-    packages: std::collections::HashMap<String, LockFileEntry>,
+    packages: BTreeMap<String, LockFileEntry>,
+    #[serde(skip)]
+    path: PathBuf,
 }
 
 impl LockFile {
     pub(crate) fn new() -> Self {
         LockFile {
             version: env!("CARGO_PKG_VERSION").to_string(),
-            packages: std::collections::HashMap::new(),
+            packages: BTreeMap::new(),
+            path: PathBuf::from("snpm.lockd"),
+        }
+    }
+
+    pub(crate) fn new_in(root: &Path) -> Self {
+        LockFile {
+            path: root.join("snpm.lockd"),
+            ..Self::new()
         }
     }
 
-    pub(crate) fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let lockfile_path = Path::new("snpm.lockd");
+    pub(crate) fn load_from(root: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let lockfile_path = root.join("snpm.lockd");
         if !lockfile_path.exists() {
-            return Ok(Self::new());
+            return Ok(Self::new_in(root));
         }
-        let content = fs::read_to_string(lockfile_path)?;
-        Ok(serde_json::from_str(&content)?)
+        let content = fs::read_to_string(&lockfile_path)?;
+        let mut lockfile: LockFile = serde_json::from_str(&content)?;
+
+        // Lockfiles written before entries were keyed by location used
+        // `name@range` keys, which can't be placed in a tree.
+        lockfile.packages.retain(|key, _| key.starts_with("node_modules/"));
+        lockfile.path = lockfile_path;
+
+        Ok(lockfile)
     }
 
     pub(crate) fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
         let content = serde_json::to_string_pretty(self)?;
-        fs::write("snpm.lockd", content)?;
+        fs::write(&self.path, content)?;
         Ok(())
     }
 
-    pub(crate) fn add_package(
-        &mut self,
-        name: String,
-        version: String,
-        tarball_url: Option<String>,
-        use_npm_fallback: bool,
-        resolved_version: String,
-    ) {
-        let key = format!("{}@{}", name, version);
-        self.packages.insert(
-            key,
-            LockFileEntry {
-                name,
-                version,
-                tarball_url,
-                use_npm_fallback,
-                resolved_version,
-            },
-        );
+    pub(crate) fn clear(&mut self) {
+        self.packages.clear();
+    }
+
+    pub(crate) fn insert(&mut self, location: String, entry: LockFileEntry) {
+        self.packages.insert(location, entry);
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&String, &LockFileEntry)> {
+        self.packages.iter()
     }
 
     pub(crate) fn should_use_npm(&self, name: &str, version: &str) -> bool {
-        let key = format!("{}@{}", name, version);
         self.packages
-            .get(&key)
-            .map_or(false, |entry| entry.use_npm_fallback)
+            .get(&format!("node_modules/{}", name))
+            .is_some_and(|entry| entry.use_npm_fallback && entry.version == version)
     }
 }
@@ -1,3 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod utils;
 pub mod info;
 pub mod lockfile;
+pub mod config;
+pub mod workspace;
+pub mod range;
+pub mod spec;
+pub mod platform;
+pub mod overrides;
@@ -0,0 +1,313 @@
+use serde_json::Value;
+use crate::utils::range;
+
+/// One step of an override path: a package, optionally limited to versions
+/// in a range, or `**` for any number of packages in between.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Anywhere,
+    Package { name: String, range: Option<String> },
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Matched against the chain of packages from the root down to the
+    /// dependency being resolved.
+    path: Vec<Segment>,
+    spec: String,
+    /// The rule as written, such as `overrides: foo > bar` or
+    /// `resolutions: foo/bar`.
+    source: String,
+}
+
+/// The `overrides` (npm) and `resolutions` (yarn) of the root package.json,
+/// which replace the spec of matching dependencies anywhere in the tree.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    rules: Vec<Rule>,
+}
+
+/// An override that applies to a dependency.
+pub struct Applied {
+    pub spec: String,
+    /// Identifies the rule and its spec, recorded in the lockfile so that
+    /// changing or removing the rule invalidates the locked entry.
+    pub label: String,
+}
+
+impl Overrides {
+    /// Reads both fields of the root manifest. `$name` values refer to the
+    /// root's own spec for `name`.
+    pub fn from_manifest(manifest: &Value) -> Result<Self, String> {
+        let mut rules = vec![];
+
+        if let Some(overrides) = manifest.get("overrides").and_then(|o| o.as_object()) {
+            parse_npm(overrides, &[], "overrides:", manifest, &mut rules)?;
+        }
+
+        if let Some(resolutions) = manifest.get("resolutions").and_then(|r| r.as_object()) {
+            for (key, value) in resolutions {
+                let Some(spec) = value.as_str() else {
+                    return Err(format!("Resolution for '{}' must be a string", key));
+                };
+                rules.push(Rule {
+                    path: parse_yarn_path(key),
+                    spec: resolve_reference(spec, manifest)?,
+                    source: format!("resolutions: {}", key),
+                });
+            }
+        }
+
+        Ok(Overrides { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Finds the override for dependency `name`, required through `chain`
+    /// (the `(name, version)` of each package from the root down). When
+    /// several rules match, the one with the most specific path wins.
+    /// `version` is called only when a rule limits the target to a range; it
+    /// returns the version the dependency would resolve to otherwise.
+    pub fn find(
+        &self,
+        chain: &[(String, String)],
+        name: &str,
+        mut version: impl FnMut() -> Option<String>,
+    ) -> Option<Applied> {
+        let mut best: Option<&Rule> = None;
+        let mut natural_version: Option<Option<String>> = None;
+
+        for rule in &self.rules {
+            let Some((Segment::Package { name: target, range }, ancestors)) = rule.path.split_last() else {
+                continue;
+            };
+            if target != name || !matches_chain(ancestors, chain) {
+                continue;
+            }
+            if let Some(range) = range {
+                let resolved = natural_version.get_or_insert_with(&mut version);
+                if !resolved.as_deref().is_some_and(|v| range::satisfies(v, range)) {
+                    continue;
+                }
+            }
+
+            let specificity = |rule: &Rule| rule.path.iter().filter(|s| **s != Segment::Anywhere).count();
+            if best.is_none_or(|current| specificity(rule) > specificity(current)) {
+                best = Some(rule);
+            }
+        }
+
+        best.map(|rule| Applied {
+            spec: rule.spec.clone(),
+            label: format!("{} = {}", rule.source, rule.spec),
+        })
+    }
+}
+
+/// npm overrides nest: `{ "foo": { ".": "1.0.0", "bar": "2.0.0" } }` sets
+/// foo itself to 1.0.0 and bar anywhere below foo to 2.0.0.
+fn parse_npm(
+    overrides: &serde_json::Map<String, Value>,
+    prefix: &[Segment],
+    source: &str,
+    manifest: &Value,
+    rules: &mut Vec<Rule>,
+) -> Result<(), String> {
+    for (key, value) in overrides {
+        if key == "." {
+            let Some(spec) = value.as_str() else {
+                return Err(format!("Override '{} .' must be a string", source));
+            };
+            if prefix.is_empty() {
+                return Err("Override '.' must be nested under a package".to_string());
+            }
+            rules.push(Rule {
+                path: prefix.to_vec(),
+                spec: resolve_reference(spec, manifest)?,
+                source: source.to_string(),
+            });
+            continue;
+        }
+
+        let mut path = prefix.to_vec();
+        path.push(Segment::Anywhere);
+        path.push(parse_selector(key));
+        let nested_source = if prefix.is_empty() {
+            format!("{} {}", source, key)
+        } else {
+            format!("{} > {}", source, key)
+        };
+
+        match value {
+            Value::String(spec) => rules.push(Rule {
+                path,
+                spec: resolve_reference(spec, manifest)?,
+                source: nested_source,
+            }),
+            Value::Object(nested) => parse_npm(nested, &path, &nested_source, manifest, rules)?,
+            _ => return Err(format!("Override '{}' must be a string or an object", key)),
+        }
+    }
+
+    Ok(())
+}
+
+/// yarn paths are `/`-separated from the root, such as `foo/bar` for bar
+/// as a dependency of foo or `**/bar` for bar anywhere. A bare name
+/// applies anywhere.
+fn parse_yarn_path(key: &str) -> Vec<Segment> {
+    let mut parts: Vec<String> = vec![];
+    for part in key.split('/') {
+        match parts.last_mut() {
+            Some(last) if last.starts_with('@') && !last.contains('/') => {
+                last.push('/');
+                last.push_str(part);
+            }
+            _ => parts.push(part.to_string()),
+        }
+    }
+
+    let mut path: Vec<Segment> = parts
+        .iter()
+        .map(|part| if part == "**" { Segment::Anywhere } else { parse_selector(part) })
+        .collect();
+    if path.len() == 1 {
+        path.insert(0, Segment::Anywhere);
+    }
+    path
+}
+
+/// `name` or `name@range`, keeping the `@` of scoped names.
+fn parse_selector(selector: &str) -> Segment {
+    let search_from = usize::from(selector.starts_with('@'));
+    match selector[search_from..].find('@') {
+        Some(at) => Segment::Package {
+            name: selector[..at + search_from].to_string(),
+            range: Some(selector[at + search_from + 1..].to_string()),
+        },
+        None => Segment::Package {
+            name: selector.to_string(),
+            range: None,
+        },
+    }
+}
+
+/// `$foo` stands for the spec the root package.json gives `foo`.
+fn resolve_reference(spec: &str, manifest: &Value) -> Result<String, String> {
+    let Some(name) = spec.strip_prefix('$') else {
+        return Ok(spec.to_string());
+    };
+
+    ["dependencies", "devDependencies", "optionalDependencies", "peerDependencies"]
+        .iter()
+        .find_map(|field| manifest[field][name].as_str())
+        .map(String::from)
+        .ok_or_else(|| format!("Override '{}' refers to '{}', which is not a dependency of the root package", spec, name))
+}
+
+fn matches_chain(path: &[Segment], chain: &[(String, String)]) -> bool {
+    match path.split_first() {
+        None => chain.is_empty(),
+        Some((Segment::Anywhere, rest)) => (0..=chain.len()).any(|skip| matches_chain(rest, &chain[skip..])),
+        Some((Segment::Package { name, range }, rest)) => match chain.split_first() {
+            Some(((chain_name, version), chain_rest)) => {
+                chain_name == name
+                    && range.as_ref().is_none_or(|range| range::satisfies(version, range))
+                    && matches_chain(rest, chain_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chain(packages: &[(&str, &str)]) -> Vec<(String, String)> {
+        packages.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect()
+    }
+
+    fn find(overrides: &Overrides, packages: &[(&str, &str)], name: &str) -> Option<String> {
+        overrides.find(&chain(packages), name, || None).map(|applied| applied.spec)
+    }
+
+    #[test]
+    fn applies_top_level_overrides_anywhere() {
+        let overrides = Overrides::from_manifest(&json!({ "overrides": { "bar": "2.0.0" } })).unwrap();
+        assert_eq!(find(&overrides, &[], "bar").as_deref(), Some("2.0.0"));
+        assert_eq!(find(&overrides, &[("foo", "1.0.0"), ("baz", "1.0.0")], "bar").as_deref(), Some("2.0.0"));
+        assert_eq!(find(&overrides, &[], "foo"), None);
+    }
+
+    #[test]
+    fn most_specific_path_wins() {
+        let overrides = Overrides::from_manifest(&json!({
+            "overrides": {
+                "bar": "2.0.0",
+                "foo": { ".": "1.5.0", "bar": "3.0.0" },
+            },
+        }))
+        .unwrap();
+        assert_eq!(find(&overrides, &[("foo", "1.5.0")], "bar").as_deref(), Some("3.0.0"));
+        assert_eq!(find(&overrides, &[("foo", "1.5.0"), ("qux", "1.0.0")], "bar").as_deref(), Some("3.0.0"));
+        assert_eq!(find(&overrides, &[("qux", "1.0.0")], "bar").as_deref(), Some("2.0.0"));
+        assert_eq!(find(&overrides, &[], "foo").as_deref(), Some("1.5.0"));
+    }
+
+    #[test]
+    fn matches_ranges_on_parents_and_targets() {
+        let overrides = Overrides::from_manifest(&json!({
+            "overrides": {
+                "foo@^1": { "bar": "1.0.0" },
+                "baz@<2": "2.0.0",
+            },
+        }))
+        .unwrap();
+        assert_eq!(find(&overrides, &[("foo", "1.2.0")], "bar").as_deref(), Some("1.0.0"));
+        assert_eq!(find(&overrides, &[("foo", "2.0.0")], "bar"), None);
+
+        let resolve = |version: &'static str| {
+            overrides
+                .find(&[], "baz", || Some(version.to_string()))
+                .map(|applied| applied.spec)
+        };
+        assert_eq!(resolve("1.9.0").as_deref(), Some("2.0.0"));
+        assert_eq!(resolve("2.1.0"), None);
+    }
+
+    #[test]
+    fn resolves_references_to_root_dependencies() {
+        let manifest = json!({
+            "dependencies": { "bar": "^4.1.0" },
+            "overrides": { "foo": { "bar": "$bar" } },
+        });
+        let overrides = Overrides::from_manifest(&manifest).unwrap();
+        assert_eq!(find(&overrides, &[("foo", "1.0.0")], "bar").as_deref(), Some("^4.1.0"));
+
+        let missing = json!({ "overrides": { "bar": "$bar" } });
+        assert!(Overrides::from_manifest(&missing).is_err());
+    }
+
+    #[test]
+    fn reads_yarn_resolutions() {
+        let overrides = Overrides::from_manifest(&json!({
+            "resolutions": {
+                "bar": "1.0.0",
+                "foo/@scope/bar": "2.0.0",
+                "**/baz": "3.0.0",
+            },
+        }))
+        .unwrap();
+        assert_eq!(find(&overrides, &[("qux", "1.0.0")], "bar").as_deref(), Some("1.0.0"));
+        assert_eq!(find(&overrides, &[("foo", "1.0.0")], "@scope/bar").as_deref(), Some("2.0.0"));
+        assert_eq!(find(&overrides, &[("qux", "1.0.0"), ("foo", "1.0.0")], "@scope/bar"), None);
+        assert_eq!(find(&overrides, &[("qux", "1.0.0")], "baz").as_deref(), Some("3.0.0"));
+
+        let applied = overrides.find(&chain(&[("foo", "1.0.0")]), "@scope/bar", || None).unwrap();
+        assert_eq!(applied.label, "resolutions: foo/@scope/bar = 2.0.0");
+    }
+}
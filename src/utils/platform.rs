@@ -0,0 +1,178 @@
+use std::env::consts;
+use std::fs;
+use serde_json::Value;
+use crate::utils::config::Config;
+
+/// The platform packages are installed for, in the terms npm uses for the
+/// `os`, `cpu` and `libc` fields of a package.json.
+#[derive(Debug, Clone)]
+pub struct Platform {
+    pub os: String,
+    pub cpu: String,
+    pub libc: Option<String>,
+}
+
+impl Platform {
+    /// The platform snpm is running on.
+    pub fn current() -> Self {
+        let os = match consts::OS {
+            "macos" => "darwin",
+            "windows" => "win32",
+            other => other,
+        };
+        let cpu = match consts::ARCH {
+            "x86_64" => "x64",
+            "x86" => "ia32",
+            "aarch64" => "arm64",
+            "powerpc64" => "ppc64",
+            "loongarch64" => "loong64",
+            other => other,
+        };
+
+        Platform {
+            os: os.to_string(),
+            cpu: cpu.to_string(),
+            libc: (os == "linux").then(detect_libc),
+        }
+    }
+
+    /// The current platform with the `os`, `cpu` and `libc` settings
+    /// applied, which lets a project install the packages of another
+    /// platform, for example when building a Docker image.
+    pub fn from_config(config: &Config) -> Self {
+        let mut platform = Self::current();
+        if let Some(os) = config.get("os") {
+            platform.os = os.to_string();
+        }
+        if let Some(cpu) = config.get("cpu") {
+            platform.cpu = cpu.to_string();
+        }
+        if let Some(libc) = config.get("libc") {
+            platform.libc = Some(libc.to_string());
+        }
+        platform
+    }
+
+    /// Checks a package's `os`, `cpu` and `libc` fields. Entries starting
+    /// with `!` exclude a platform; otherwise the platform must be listed.
+    /// `libc` only applies on Linux.
+    pub fn check(&self, manifest: &Value) -> Result<(), String> {
+        let mut supported = allows(manifest.get("os"), &self.os) && allows(manifest.get("cpu"), &self.cpu);
+        if self.os == "linux" {
+            if let Some(libc) = &self.libc {
+                supported = supported && allows(manifest.get("libc"), libc);
+            }
+        }
+
+        if supported {
+            return Ok(());
+        }
+
+        let wanted: Vec<String> = ["os", "cpu", "libc"]
+            .iter()
+            .filter_map(|field| manifest.get(*field).map(|value| format!("{}: {}", field, value)))
+            .collect();
+        Err(format!(
+            "Unsupported platform for {}@{}: wanted {} (current: os: {}, cpu: {}{})",
+            manifest["name"].as_str().unwrap_or("unknown"),
+            manifest["version"].as_str().unwrap_or("unknown"),
+            wanted.join(", "),
+            self.os,
+            self.cpu,
+            self.libc.as_ref().map(|libc| format!(", libc: {}", libc)).unwrap_or_default()
+        ))
+    }
+}
+
+fn allows(field: Option<&Value>, current: &str) -> bool {
+    let entries: Vec<&str> = match field {
+        Some(Value::String(entry)) => vec![entry.as_str()],
+        Some(Value::Array(entries)) => entries.iter().filter_map(|e| e.as_str()).collect(),
+        _ => return true,
+    };
+
+    if entries.iter().any(|entry| *entry == format!("!{}", current)) {
+        return false;
+    }
+
+    let listed: Vec<&&str> = entries.iter().filter(|entry| !entry.starts_with('!')).collect();
+    listed.is_empty() || listed.iter().any(|entry| **entry == current || **entry == "any")
+}
+
+/// musl systems ship their dynamic loader as `/lib/ld-musl-<arch>.so.1`.
+fn detect_libc() -> String {
+    let musl = fs::read_dir("/lib").is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.file_name().to_string_lossy().starts_with("ld-musl-"))
+    });
+
+    if musl { "musl" } else { "glibc" }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn linux_glibc() -> Platform {
+        Platform {
+            os: "linux".to_string(),
+            cpu: "x64".to_string(),
+            libc: Some("glibc".to_string()),
+        }
+    }
+
+    #[test]
+    fn allows_listed_platforms() {
+        let platform = linux_glibc();
+        assert!(platform.check(&json!({})).is_ok());
+        assert!(platform.check(&json!({ "os": ["darwin", "linux"], "cpu": "x64" })).is_ok());
+        assert!(platform.check(&json!({ "os": "any" })).is_ok());
+        assert!(platform.check(&json!({ "os": ["darwin"] })).is_err());
+        assert!(platform.check(&json!({ "cpu": ["arm64"] })).is_err());
+    }
+
+    #[test]
+    fn denies_negated_platforms() {
+        let platform = linux_glibc();
+        assert!(platform.check(&json!({ "os": ["!win32"] })).is_ok());
+        assert!(platform.check(&json!({ "os": ["!linux"] })).is_err());
+        assert!(platform.check(&json!({ "os": ["linux", "!linux"] })).is_err());
+        assert!(platform.check(&json!({ "cpu": ["!arm64", "x64"] })).is_ok());
+    }
+
+    #[test]
+    fn checks_libc_only_on_linux() {
+        let platform = linux_glibc();
+        assert!(platform.check(&json!({ "libc": ["glibc"] })).is_ok());
+        assert!(platform.check(&json!({ "libc": ["musl"] })).is_err());
+
+        let darwin = Platform {
+            os: "darwin".to_string(),
+            cpu: "arm64".to_string(),
+            libc: Some("glibc".to_string()),
+        };
+        assert!(darwin.check(&json!({ "libc": ["musl"] })).is_ok());
+    }
+
+    #[test]
+    fn describes_the_mismatch() {
+        let error = linux_glibc()
+            .check(&json!({ "name": "esb-darwin", "version": "1.0.0", "os": ["darwin"] }))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            r#"Unsupported platform for esb-darwin@1.0.0: wanted os: ["darwin"] (current: os: linux, cpu: x64, libc: glibc)"#
+        );
+    }
+
+    #[test]
+    fn applies_configured_overrides() {
+        let mut config = Config::default();
+        config.set("os", "win32");
+        config.set("cpu", "arm64");
+        let platform = Platform::from_config(&config);
+        assert_eq!((platform.os.as_str(), platform.cpu.as_str()), ("win32", "arm64"));
+    }
+}
@@ -0,0 +1,216 @@
+use semver::{Version, VersionReq};
+use serde_json::Value;
+
+/// An npm version range: `||`-separated alternatives of space-separated
+/// comparators, hyphen ranges, x-ranges and bare versions.
+#[derive(Debug, Clone)]
+pub struct Range {
+    alternatives: Vec<VersionReq>,
+}
+
+impl Range {
+    pub fn parse(range: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut alternatives = vec![];
+
+        for alternative in range.split("||") {
+            let requirement = translate(alternative.trim());
+            let parsed = VersionReq::parse(&requirement)
+                .map_err(|e| format!("Invalid version range '{}': {}", range, e))?;
+            alternatives.push(parsed);
+        }
+
+        Ok(Range { alternatives })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|req| req.matches(version))
+    }
+}
+
+/// Whether `version` satisfies `range`, treating unparseable input as a miss.
+pub fn satisfies(version: &str, range: &str) -> bool {
+    match (Version::parse(version.trim_start_matches('v')), Range::parse(range)) {
+        (Ok(version), Ok(range)) => range.matches(&version),
+        _ => false,
+    }
+}
+
+/// Picks the version of a packument that `range` refers to, which may be a
+/// dist-tag. Like npm, the `latest` tag wins when it satisfies the range.
+pub fn select_version(metadata: &Value, range: &str) -> Option<String> {
+    let range = if range.trim().is_empty() { "latest" } else { range.trim() };
+
+    if let Some(tagged) = metadata
+        .get("dist-tags")
+        .and_then(|tags| tags.get(range))
+        .and_then(|v| v.as_str())
+    {
+        return Some(tagged.to_string());
+    }
+
+    let parsed = Range::parse(range).ok()?;
+    let versions = metadata.get("versions")?.as_object()?;
+
+    if let Some(latest) = metadata
+        .get("dist-tags")
+        .and_then(|tags| tags.get("latest"))
+        .and_then(|v| v.as_str())
+    {
+        if versions.contains_key(latest)
+            && Version::parse(latest).is_ok_and(|v| parsed.matches(&v))
+        {
+            return Some(latest.to_string());
+        }
+    }
+
+    versions
+        .keys()
+        .filter_map(|v| Version::parse(v).ok())
+        .filter(|v| parsed.matches(v))
+        .max()
+        .map(|v| v.to_string())
+}
+
+fn translate(alternative: &str) -> String {
+    if alternative.is_empty() {
+        return "*".to_string();
+    }
+
+    let tokens: Vec<&str> = alternative.split_whitespace().collect();
+    if tokens.len() == 3 && tokens[1] == "-" {
+        return format!(">={}, <={}", normalize(tokens[0]), normalize(tokens[2]));
+    }
+
+    let mut comparators = vec![];
+    let mut pending_operator = String::new();
+
+    for token in tokens {
+        if token.chars().all(|c| "<>=~^".contains(c)) {
+            pending_operator.push_str(token);
+            continue;
+        }
+
+        let token = format!("{}{}", pending_operator, token);
+        pending_operator.clear();
+
+        let split = token
+            .find(|c: char| !"<>=~^".contains(c))
+            .unwrap_or(token.len());
+        let (operator, version) = token.split_at(split);
+        let version = normalize(version);
+
+        comparators.push(match (operator, version.as_str()) {
+            (_, "*") => "*".to_string(),
+            ("", version) => format!("={}", version),
+            (operator, version) => format!("{}{}", operator, version),
+        });
+    }
+
+    comparators.join(", ")
+}
+
+/// Strips a leading `v` and truncates x-ranges (`1.2.x` → `1.2`).
+fn normalize(version: &str) -> String {
+    let version = version.trim_start_matches('v').trim_start_matches('=');
+    let (core, pre) = match version.split_once(['-', '+']) {
+        Some((core, _)) => (core, &version[core.len()..]),
+        None => (version, ""),
+    };
+
+    let mut parts = vec![];
+    for part in core.split('.') {
+        if part.is_empty() || part == "x" || part == "X" || part == "*" {
+            break;
+        }
+        parts.push(part);
+    }
+
+    if parts.is_empty() {
+        return "*".to_string();
+    }
+    if parts.len() < 3 {
+        return parts.join(".");
+    }
+    format!("{}{}", parts.join("."), pre)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn matches_alternatives() {
+        assert!(satisfies("1.4.0", "^1.2.0 || ^2.0.0"));
+        assert!(satisfies("2.1.0", "^1.2.0 || ^2.0.0"));
+        assert!(!satisfies("3.0.0", "^1.2.0 || ^2.0.0"));
+        assert!(satisfies("0.9.0", "<1 || >=3"));
+        assert!(!satisfies("1.0.0", "<1 || >=3"));
+    }
+
+    #[test]
+    fn matches_hyphen_ranges() {
+        assert!(satisfies("1.2.3", "1.2.3 - 2.3.4"));
+        assert!(satisfies("2.3.4", "1.2.3 - 2.3.4"));
+        assert!(!satisfies("2.3.5", "1.2.3 - 2.3.4"));
+        assert!(!satisfies("1.2.2", "1.2.3 - 2.3.4"));
+        assert!(satisfies("2.3.9", "1.2 - 2.3"));
+        assert!(!satisfies("2.4.0", "1.2 - 2.3"));
+    }
+
+    #[test]
+    fn matches_x_ranges_and_bare_versions() {
+        assert!(satisfies("1.2.9", "1.2.x"));
+        assert!(!satisfies("1.3.0", "1.2.x"));
+        assert!(satisfies("1.9.0", "1.x"));
+        assert!(satisfies("1.9.0", "1"));
+        assert!(satisfies("5.0.0", "*"));
+        assert!(satisfies("5.0.0", "x"));
+        assert!(satisfies("5.0.0", ""));
+        assert!(satisfies("1.2.3", "1.2.3"));
+        assert!(satisfies("1.2.3", "=v1.2.3"));
+        assert!(!satisfies("1.2.4", "1.2.3"));
+    }
+
+    #[test]
+    fn joins_separated_operators() {
+        assert!(satisfies("1.5.0", ">= 1.2.0 < 2"));
+        assert!(!satisfies("2.0.0", ">= 1.2.0 < 2"));
+        assert!(satisfies("1.2.5", "~ 1.2.0"));
+    }
+
+    #[test]
+    fn rejects_unparseable_input() {
+        assert!(!satisfies("not-a-version", "^1.0.0"));
+        assert!(!satisfies("1.0.0", "latest"));
+        assert!(Range::parse("^1 || nonsense").is_err());
+    }
+
+    fn packument() -> Value {
+        json!({
+            "dist-tags": { "latest": "1.2.0", "next": "2.0.0-beta.1" },
+            "versions": {
+                "1.0.0": {},
+                "1.2.0": {},
+                "1.3.0": {},
+                "2.0.0-beta.1": {},
+            },
+        })
+    }
+
+    #[test]
+    fn selects_dist_tags() {
+        assert_eq!(select_version(&packument(), "next").as_deref(), Some("2.0.0-beta.1"));
+        assert_eq!(select_version(&packument(), "latest").as_deref(), Some("1.2.0"));
+        assert_eq!(select_version(&packument(), "").as_deref(), Some("1.2.0"));
+        assert_eq!(select_version(&packument(), "canary"), None);
+    }
+
+    #[test]
+    fn prefers_latest_when_it_satisfies_the_range() {
+        assert_eq!(select_version(&packument(), "^1.0.0").as_deref(), Some("1.2.0"));
+        assert_eq!(select_version(&packument(), "~1.3.0").as_deref(), Some("1.3.0"));
+        assert_eq!(select_version(&packument(), "<1.2.0").as_deref(), Some("1.0.0"));
+        assert_eq!(select_version(&packument(), "^3"), None);
+    }
+}
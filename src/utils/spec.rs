@@ -0,0 +1,249 @@
+/// Where a dependency comes from, as written in a package.json.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Spec {
+    /// A version, range or dist-tag of `name` on the registry. Aliases
+    /// (`npm:other@^1`) name the real package here.
+    Registry { name: String, range: String },
+    /// A member of the current workspace (`workspace:*`).
+    Workspace { range: String },
+    /// A git repository, optionally at a branch, tag, commit or
+    /// `semver:<range>` of its tags.
+    Git { url: String, committish: Option<String> },
+    /// A tarball at an `http(s)` URL.
+    Tarball { url: String },
+    /// A directory or tarball on disk (`file:../lib`, `./lib`).
+    File { path: String },
+    /// A directory to symlink (`link:../lib`).
+    Link { path: String },
+}
+
+impl Spec {
+    /// Parses the spec of dependency `name`, following the protocols npm
+    /// accepts. Anything unrecognised is taken as a registry range.
+    pub fn parse(name: &str, raw: &str) -> Spec {
+        let raw = raw.trim();
+
+        if let Some(aliased) = raw.strip_prefix("npm:") {
+            let search_from = usize::from(aliased.starts_with('@'));
+            return match aliased[search_from..].find('@') {
+                Some(at) => Spec::Registry {
+                    name: aliased[..at + search_from].to_string(),
+                    range: aliased[at + search_from + 1..].to_string(),
+                },
+                None => Spec::Registry {
+                    name: aliased.to_string(),
+                    range: "latest".to_string(),
+                },
+            };
+        }
+        if let Some(range) = raw.strip_prefix("workspace:") {
+            return Spec::Workspace { range: range.to_string() };
+        }
+        if let Some(path) = raw.strip_prefix("link:") {
+            return Spec::Link { path: path.to_string() };
+        }
+        if let Some(path) = raw.strip_prefix("file:") {
+            return Spec::File { path: path.to_string() };
+        }
+        if is_path(raw) {
+            return Spec::File { path: raw.to_string() };
+        }
+
+        let (location, committish) = match raw.split_once('#') {
+            Some((location, committish)) if !committish.is_empty() => (location, Some(committish.to_string())),
+            Some((location, _)) => (location, None),
+            None => (raw, None),
+        };
+
+        for (prefix, host) in [
+            ("github:", "https://github.com/"),
+            ("gitlab:", "https://gitlab.com/"),
+            ("bitbucket:", "https://bitbucket.org/"),
+        ] {
+            if let Some(repo) = location.strip_prefix(prefix) {
+                return Spec::Git {
+                    url: format!("{}{}.git", host, repo.trim_end_matches(".git")),
+                    committish,
+                };
+            }
+        }
+        if let Some(id) = location.strip_prefix("gist:") {
+            let id = id.rsplit('/').next().unwrap_or(id);
+            return Spec::Git {
+                url: format!("https://gist.github.com/{}.git", id),
+                committish,
+            };
+        }
+
+        for prefix in ["git+ssh://", "git+https://", "git+http://", "git+file://", "git://", "ssh://"] {
+            if location.starts_with(prefix) {
+                return Spec::Git {
+                    url: scp_to_ssh_url(location.trim_start_matches("git+")),
+                    committish,
+                };
+            }
+        }
+
+        if location.starts_with("http://") || location.starts_with("https://") {
+            if location.ends_with(".git") {
+                return Spec::Git {
+                    url: location.to_string(),
+                    committish,
+                };
+            }
+            return Spec::Tarball { url: raw.to_string() };
+        }
+
+        if is_github_shorthand(location) {
+            return Spec::Git {
+                url: format!("https://github.com/{}.git", location.trim_end_matches(".git")),
+                committish,
+            };
+        }
+
+        Spec::Registry {
+            name: name.to_string(),
+            range: if raw.is_empty() { "latest".to_string() } else { raw.to_string() },
+        }
+    }
+}
+
+/// Rewrites scp-style `ssh://git@host:user/repo`, which npm accepts but git
+/// doesn't, to `ssh://git@host/user/repo`. A numeric port is left alone.
+fn scp_to_ssh_url(url: &str) -> String {
+    let Some(rest) = url.strip_prefix("ssh://") else {
+        return url.to_string();
+    };
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let host_start = authority.find('@').map_or(0, |at| at + 1);
+    match authority[host_start..].find(':') {
+        Some(colon) => {
+            let colon = host_start + colon;
+            let after = &authority[colon + 1..];
+            if !after.is_empty() && after.chars().all(|c| c.is_ascii_digit()) {
+                return url.to_string();
+            }
+            format!("ssh://{}/{}", &rest[..colon], &rest[colon + 1..])
+        }
+        None => url.to_string(),
+    }
+}
+
+fn is_path(raw: &str) -> bool {
+    raw == "."
+        || raw == ".."
+        || raw.starts_with("./")
+        || raw.starts_with("../")
+        || raw.starts_with('/')
+        || raw.starts_with("~/")
+        || raw.starts_with(".\\")
+        || raw.starts_with("..\\")
+        || (raw.as_bytes().get(1) == Some(&b':') && raw.as_bytes().get(2).is_some_and(|b| *b == b'\\' || *b == b'/'))
+}
+
+/// `user/repo`, which npm reads as a GitHub repository.
+fn is_github_shorthand(raw: &str) -> bool {
+    let Some((user, repo)) = raw.split_once('/') else {
+        return false;
+    };
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    };
+    !user.starts_with('@') && valid(user) && valid(repo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(url: &str, committish: Option<&str>) -> Spec {
+        Spec::Git {
+            url: url.to_string(),
+            committish: committish.map(String::from),
+        }
+    }
+
+    #[test]
+    fn parses_registry_ranges_and_aliases() {
+        assert_eq!(
+            Spec::parse("left-pad", "^1.2.0"),
+            Spec::Registry { name: "left-pad".into(), range: "^1.2.0".into() }
+        );
+        assert_eq!(
+            Spec::parse("left-pad", ""),
+            Spec::Registry { name: "left-pad".into(), range: "latest".into() }
+        );
+        assert_eq!(
+            Spec::parse("pad", "npm:left-pad@^1"),
+            Spec::Registry { name: "left-pad".into(), range: "^1".into() }
+        );
+        assert_eq!(
+            Spec::parse("util", "npm:@demo/util"),
+            Spec::Registry { name: "@demo/util".into(), range: "latest".into() }
+        );
+        assert_eq!(
+            Spec::parse("util", "npm:@demo/util@2.x"),
+            Spec::Registry { name: "@demo/util".into(), range: "2.x".into() }
+        );
+    }
+
+    #[test]
+    fn parses_workspace_file_and_link_specs() {
+        assert_eq!(Spec::parse("a", "workspace:^"), Spec::Workspace { range: "^".into() });
+        assert_eq!(Spec::parse("a", "file:../a"), Spec::File { path: "../a".into() });
+        assert_eq!(Spec::parse("a", "./a.tgz"), Spec::File { path: "./a.tgz".into() });
+        assert_eq!(Spec::parse("a", "~/a"), Spec::File { path: "~/a".into() });
+        assert_eq!(Spec::parse("a", "C:\\a"), Spec::File { path: "C:\\a".into() });
+        assert_eq!(Spec::parse("a", "link:../a"), Spec::Link { path: "../a".into() });
+    }
+
+    #[test]
+    fn parses_git_hosts_and_urls() {
+        assert_eq!(
+            Spec::parse("a", "github:user/repo#v1.0.0"),
+            git("https://github.com/user/repo.git", Some("v1.0.0"))
+        );
+        assert_eq!(Spec::parse("a", "user/repo"), git("https://github.com/user/repo.git", None));
+        assert_eq!(Spec::parse("a", "gitlab:user/repo#"), git("https://gitlab.com/user/repo.git", None));
+        assert_eq!(
+            Spec::parse("a", "gist:user/abc123"),
+            git("https://gist.github.com/abc123.git", None)
+        );
+        assert_eq!(
+            Spec::parse("a", "git+https://host/repo.git#semver:^1"),
+            git("https://host/repo.git", Some("semver:^1"))
+        );
+        assert_eq!(
+            Spec::parse("a", "git+file:///tmp/repo.git"),
+            git("file:///tmp/repo.git", None)
+        );
+        assert_eq!(Spec::parse("a", "https://host/repo.git"), git("https://host/repo.git", None));
+    }
+
+    #[test]
+    fn rewrites_scp_style_ssh_urls() {
+        assert_eq!(
+            Spec::parse("a", "git+ssh://git@github.com:user/repo.git#main"),
+            git("ssh://git@github.com/user/repo.git", Some("main"))
+        );
+        assert_eq!(
+            Spec::parse("a", "git+ssh://git@host:2222/user/repo.git"),
+            git("ssh://git@host:2222/user/repo.git", None)
+        );
+        assert_eq!(
+            Spec::parse("a", "ssh://git@host/user/repo.git"),
+            git("ssh://git@host/user/repo.git", None)
+        );
+    }
+
+    #[test]
+    fn parses_tarball_urls() {
+        assert_eq!(
+            Spec::parse("a", "https://host/a-1.0.0.tgz"),
+            Spec::Tarball { url: "https://host/a-1.0.0.tgz".into() }
+        );
+    }
+}
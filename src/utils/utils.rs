@@ -1,5 +1,7 @@
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::{env, fs};
+use std::path::{Component, Path, PathBuf};
+use serde_json::Value;
+use crate::utils::config::Config;
 
 pub const ASCII_ART: &str = r"
      _______..__   __. .______   .___  ___. 
@@ -23,9 +25,28 @@ pub fn get_framework_info(command: &str) -> Option<(&'static str, &'static str)>
     }
 }
 
+/// The snpm cache: `SNPM_CACHE_DIR` when set, then the `cache` setting,
+/// then `~/.snpm_cache` (`%LOCALAPPDATA%\snpm-cache` on Windows).
 pub fn get_cache_directory() -> PathBuf {
+    cache_directory(&Config::load())
+}
+
+/// [`get_cache_directory`] with an already loaded `config`.
+pub fn cache_directory(config: &Config) -> PathBuf {
+    if let Some(dir) = env::var_os("SNPM_CACHE_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = config.get("cache") {
+        return match dir.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+            None => PathBuf::from(dir),
+        };
+    }
+
     if cfg!(target_os = "windows") {
-        PathBuf::from(r"C:\snpm_cache")
+        dirs::data_local_dir()
+            .expect("Failed to determine the local app data directory")
+            .join("snpm-cache")
     } else {
         dirs::home_dir()
             .expect("Failed to determine home directory")
@@ -33,6 +54,13 @@ pub fn get_cache_directory() -> PathBuf {
     }
 }
 
+pub fn read_json(path: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e).into())
+}
+
 pub fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
     if !src.exists() {
         return Err(std::io::Error::new(
@@ -61,3 +89,122 @@ pub fn copy_dir_contents(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
 
     Ok(())
 }
+
+/// Matches a script name against an npm-run-all style pattern, where `*`
+/// stays within one `:`-separated segment and `**` spans any number of them.
+pub fn matches_task_pattern(pattern: &str, name: &str) -> bool {
+    matches_segment_pattern(pattern, name, ':')
+}
+
+/// Matches a `/`-separated path against a glob such as `lib/**/*.js`.
+pub fn matches_path_pattern(pattern: &str, path: &str) -> bool {
+    matches_segment_pattern(pattern, path, '/')
+}
+
+fn matches_segment_pattern(pattern: &str, name: &str, separator: char) -> bool {
+    let pattern_segments: Vec<&str> = pattern.split(separator).collect();
+    let name_segments: Vec<&str> = name.split(separator).collect();
+    match_segments(&pattern_segments, &name_segments)
+}
+
+fn match_segments(pattern: &[&str], name: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&"**", rest)) => (0..=name.len()).any(|skip| match_segments(rest, &name[skip..])),
+        Some((segment, rest)) => match name.split_first() {
+            Some((first, remaining)) => matches_wildcard(segment, first) && match_segments(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+pub fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, tail)) => {
+            let Some(remaining) = text.strip_prefix(head) else {
+                return false;
+            };
+            (0..=remaining.len())
+                .filter(|&i| remaining.is_char_boundary(i))
+                .any(|i| matches_wildcard(tail, &remaining[i..]))
+        }
+    }
+}
+
+/// Levenshtein distance between two strings, used for "did you mean" hints.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b_chars.len()]
+}
+
+/// Path to `target` from `from`, both absolute.
+pub fn relative_path(from: &Path, target: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let target_components: Vec<Component> = target.components().collect();
+    let common = from
+        .iter()
+        .zip(&target_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &target_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_wildcard("build", "build"));
+        assert!(!matches_wildcard("build", "builder"));
+        assert!(matches_wildcard("build*", "build"));
+        assert!(matches_wildcard("build*", "build-css"));
+        assert!(matches_wildcard("*-css", "build-css"));
+        assert!(matches_wildcard("b*d*s", "build-css"));
+        assert!(!matches_wildcard("b*d*x", "build-css"));
+        assert!(matches_wildcard("@scope/*", "@scope/ünïcode"));
+        assert!(matches_wildcard("*", ""));
+    }
+
+    #[test]
+    fn matches_task_patterns_by_segment() {
+        assert!(matches_task_pattern("build:*", "build:css"));
+        assert!(!matches_task_pattern("build:*", "build:css:min"));
+        assert!(!matches_task_pattern("build:*", "build"));
+        assert!(matches_task_pattern("build:**", "build:css:min"));
+        assert!(matches_task_pattern("build:**", "build"));
+        assert!(matches_task_pattern("**:min", "build:css:min"));
+        assert!(matches_task_pattern("lint", "lint"));
+    }
+
+    #[test]
+    fn measures_edit_distance() {
+        assert_eq!(edit_distance("build", "build"), 0);
+        assert_eq!(edit_distance("", "test"), 4);
+        assert_eq!(edit_distance("test", ""), 4);
+        assert_eq!(edit_distance("biuld", "build"), 2);
+        assert_eq!(edit_distance("buil", "build"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("tëst", "test"), 1);
+    }
+}
@@ -0,0 +1,485 @@
+use std::{env, fs};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use serde_json::Value;
+use crate::logger;
+use crate::utils::utils::{self, matches_wildcard};
+
+/// A workspace member package. `path` is relative to the workspace root.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    pub manifest: Value,
+}
+
+/// Reads the `workspaces` field in either its array form or the
+/// `{ "packages": [...] }` object form.
+pub fn member_patterns(json: &Value) -> Vec<String> {
+    let patterns = match json.get("workspaces") {
+        Some(Value::Array(items)) => Some(items),
+        Some(Value::Object(map)) => map.get("packages").and_then(|p| p.as_array()),
+        _ => None,
+    };
+
+    patterns
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Expands the workspace patterns of the package at `root` into member
+/// directories that contain a package.json. Patterns starting with `!`
+/// exclude matches.
+pub fn find_members(root: &Path, json: &Value) -> Vec<PathBuf> {
+    let mut members: Vec<PathBuf> = vec![];
+    let mut excluded: Vec<PathBuf> = vec![];
+
+    for pattern in member_patterns(json) {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest.to_string()),
+            None => (false, pattern),
+        };
+        let segments: Vec<&str> = pattern
+            .trim_start_matches("./")
+            .trim_end_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let mut matched = vec![];
+        expand(root, &segments, &mut matched);
+
+        if negated {
+            excluded.extend(matched);
+        } else {
+            for path in matched {
+                if path.join("package.json").is_file() && !members.contains(&path) {
+                    members.push(path);
+                }
+            }
+        }
+    }
+
+    members.retain(|path| !excluded.contains(path));
+    members.sort();
+    members
+}
+
+/// Reads the package.json of every workspace member of `root`. Members
+/// without a name can't be linked and are skipped with a warning.
+pub fn load_members(root: &Path, json: &Value) -> Vec<Member> {
+    let mut members = vec![];
+
+    for dir in find_members(root, json) {
+        let manifest = match utils::read_json(&dir.join("package.json")) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                logger::warn(&e.to_string());
+                continue;
+            }
+        };
+        let Some(name) = manifest.get("name").and_then(|n| n.as_str()) else {
+            logger::warn(&format!("Skipping workspace {} without a name", dir.display()));
+            continue;
+        };
+
+        members.push(Member {
+            name: name.to_string(),
+            version: manifest
+                .get("version")
+                .and_then(|v| v.as_str())
+                .unwrap_or("0.0.0")
+                .to_string(),
+            path: dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(),
+            manifest,
+        });
+    }
+
+    members
+}
+
+/// Finds the workspace root that `dir` belongs to: the nearest ancestor
+/// (or `dir` itself) whose `workspaces` include it.
+pub fn find_root(dir: &Path) -> Option<PathBuf> {
+    for ancestor in dir.ancestors() {
+        let Ok(json) = utils::read_json(&ancestor.join("package.json")) else {
+            continue;
+        };
+        if member_patterns(&json).is_empty() {
+            continue;
+        }
+        if ancestor == dir || find_members(ancestor, &json).iter().any(|member| member == dir) {
+            return Some(ancestor.to_path_buf());
+        }
+    }
+    None
+}
+
+/// Replaces `workspace:` ranges with real ones for publishing: `workspace:*`
+/// becomes the member's exact version, `workspace:^` and `workspace:~` gain
+/// that prefix, and an explicit range like `workspace:^1.2.0` is kept as is.
+pub fn rewrite_workspace_ranges(manifest: &mut Value, members: &[Member]) -> Result<(), String> {
+    for field in ["dependencies", "devDependencies", "optionalDependencies", "peerDependencies"] {
+        let Some(deps) = manifest.get_mut(field).and_then(|d| d.as_object_mut()) else {
+            continue;
+        };
+
+        for (name, spec) in deps.iter_mut() {
+            let Some(range) = spec.as_str().and_then(|s| s.strip_prefix("workspace:")) else {
+                continue;
+            };
+            let member = members
+                .iter()
+                .find(|member| &member.name == name)
+                .ok_or_else(|| format!("No workspace package named '{}' for {}", name, field))?;
+
+            let rewritten = match range {
+                "*" | "" => member.version.clone(),
+                "^" | "~" => format!("{}{}", range, member.version),
+                explicit => explicit.to_string(),
+            };
+            *spec = Value::String(rewritten);
+        }
+    }
+
+    Ok(())
+}
+
+fn expand(dir: &Path, segments: &[&str], matched: &mut Vec<PathBuf>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        matched.push(dir.to_path_buf());
+        return;
+    };
+
+    if *segment == "**" {
+        expand(dir, rest, matched);
+        for child in subdirectories(dir) {
+            expand(&child, segments, matched);
+        }
+        return;
+    }
+
+    if !segment.contains('*') {
+        let child = dir.join(segment);
+        if child.is_dir() {
+            expand(&child, rest, matched);
+        }
+        return;
+    }
+
+    for child in subdirectories(dir) {
+        let name = child.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if matches_wildcard(segment, name) {
+            expand(&child, rest, matched);
+        }
+    }
+}
+
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|name| name != "node_modules" && !name.starts_with('.'))
+        })
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// For each member, the indices of the members it depends on through any
+/// kind of dependency.
+pub fn dependency_graph(members: &[Member]) -> Vec<Vec<usize>> {
+    members
+        .iter()
+        .map(|member| {
+            let mut edges: Vec<usize> = vec![];
+            for field in ["dependencies", "devDependencies", "optionalDependencies", "peerDependencies"] {
+                let Some(deps) = member.manifest.get(field).and_then(|d| d.as_object()) else {
+                    continue;
+                };
+                for name in deps.keys() {
+                    if let Some(index) = members.iter().position(|m| &m.name == name) {
+                        if !edges.contains(&index) && members[index].name != member.name {
+                            edges.push(index);
+                        }
+                    }
+                }
+            }
+            edges
+        })
+        .collect()
+}
+
+/// The members `index` depends on, directly or through other members.
+pub fn dependencies_of(graph: &[Vec<usize>], index: usize) -> Vec<usize> {
+    let mut dependencies = closure(&graph[index], |i| graph[i].clone());
+    dependencies.retain(|&i| i != index);
+    dependencies
+}
+
+/// Orders `selected` members so that every member comes after the members
+/// it depends on, even through members that aren't selected. Members
+/// caught in a cycle keep their original order.
+pub fn topological_order(graph: &[Vec<usize>], selected: &[usize]) -> Vec<usize> {
+    let dependencies: Vec<Vec<usize>> = (0..graph.len()).map(|i| dependencies_of(graph, i)).collect();
+    let mut ordered: Vec<usize> = vec![];
+    let mut remaining: Vec<usize> = selected.to_vec();
+
+    while !remaining.is_empty() {
+        let ready: Vec<usize> = remaining
+            .iter()
+            .copied()
+            .filter(|&i| {
+                dependencies[i]
+                    .iter()
+                    .all(|dep| !remaining.contains(dep) || ordered.contains(dep))
+            })
+            .collect();
+
+        if ready.is_empty() {
+            logger::warn("Workspace dependency cycle detected; running the remaining packages in declaration order");
+            ordered.append(&mut remaining);
+            break;
+        }
+
+        remaining.retain(|i| !ready.contains(i));
+        ordered.extend(ready);
+    }
+
+    ordered
+}
+
+/// Selects members matching any of `filters`. A filter is a name glob
+/// (`@scope/*`), a path (`./packages/a`, `{packages/a}`) or `[ref]` for
+/// packages with files changed since a git ref. A leading `...` adds the
+/// packages that depend on the matches, a trailing `...` adds the packages
+/// they depend on, and a leading `!` excludes the matches instead.
+pub fn filter_members(root: &Path, members: &[Member], filters: &[String]) -> Result<Vec<usize>, String> {
+    let graph = dependency_graph(members);
+    let mut included: Vec<usize> = vec![];
+    let mut excluded: Vec<usize> = vec![];
+
+    for filter in filters {
+        let (negated, filter) = match filter.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, filter.as_str()),
+        };
+        let (with_dependents, filter) = match filter.strip_prefix("...") {
+            Some(rest) => (true, rest),
+            None => (false, filter),
+        };
+        let (with_dependencies, filter) = match filter.strip_suffix("...") {
+            Some(rest) => (true, rest),
+            None => (false, filter),
+        };
+
+        let mut matched: Vec<usize> = if let Some(git_ref) = filter.strip_prefix('[').and_then(|f| f.strip_suffix(']')) {
+            let changed = changed_files(root, git_ref)?;
+            (0..members.len())
+                .filter(|&i| {
+                    let dir = root.join(&members[i].path);
+                    changed.iter().any(|file| file.starts_with(&dir))
+                })
+                .collect()
+        } else if let Some(path) = filter
+            .strip_prefix('{')
+            .and_then(|f| f.strip_suffix('}'))
+            .or_else(|| (filter.starts_with("./") || filter.starts_with("../")).then_some(filter))
+        {
+            let target = normalize(&env::current_dir().unwrap_or_else(|_| root.to_path_buf()).join(path));
+            (0..members.len())
+                .filter(|&i| {
+                    let dir = root.join(&members[i].path);
+                    dir == target || dir.starts_with(&target)
+                })
+                .collect()
+        } else {
+            (0..members.len())
+                .filter(|&i| matches_wildcard(filter, &members[i].name))
+                .collect()
+        };
+
+        if with_dependencies {
+            matched = closure(&matched, |i| graph[i].clone());
+        }
+        if with_dependents {
+            matched = closure(&matched, |i| {
+                (0..members.len()).filter(|&j| graph[j].contains(&i)).collect()
+            });
+        }
+
+        if negated {
+            excluded.extend(matched);
+        } else {
+            included.extend(matched);
+        }
+    }
+
+    if filters.iter().all(|f| f.starts_with('!')) {
+        included = (0..members.len()).collect();
+    }
+
+    let mut selected: Vec<usize> = (0..members.len())
+        .filter(|i| included.contains(i) && !excluded.contains(i))
+        .collect();
+    selected.dedup();
+
+    Ok(selected)
+}
+
+fn closure(start: &[usize], next: impl Fn(usize) -> Vec<usize>) -> Vec<usize> {
+    let mut result: Vec<usize> = start.to_vec();
+    let mut queue: Vec<usize> = start.to_vec();
+
+    while let Some(index) = queue.pop() {
+        for neighbour in next(index) {
+            if !result.contains(&neighbour) {
+                result.push(neighbour);
+                queue.push(neighbour);
+            }
+        }
+    }
+
+    result
+}
+
+/// Absolute paths of files that differ from `git_ref`, including
+/// uncommitted and untracked files.
+fn changed_files(root: &Path, git_ref: &str) -> Result<Vec<PathBuf>, String> {
+    let git = |args: &[&str]| -> Result<String, String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(root)
+            .output()
+            .map_err(|e| format!("Failed to run git: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+
+    let toplevel = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim());
+    let diff = git(&["diff", "--name-only", git_ref, "--"])?;
+    let untracked = git(&["ls-files", "--others", "--exclude-standard", "--full-name"])?;
+
+    Ok(diff
+        .lines()
+        .chain(untracked.lines())
+        .filter(|line| !line.is_empty())
+        .map(|line| normalize(&toplevel.join(line)))
+        .collect())
+}
+
+/// Resolves `.` and `..` components without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn member(name: &str, dependencies: &[&str]) -> Member {
+        let dependencies: serde_json::Map<String, Value> =
+            dependencies.iter().map(|dep| (dep.to_string(), json!("workspace:*"))).collect();
+        Member {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            path: Path::new("packages").join(name.trim_start_matches("@app/")),
+            manifest: json!({ "name": name, "dependencies": dependencies }),
+        }
+    }
+
+    /// `@app/web` depends on `@app/ui`, which depends on `@app/core`;
+    /// `tools` stands alone.
+    fn members() -> Vec<Member> {
+        vec![
+            member("@app/web", &["@app/ui"]),
+            member("@app/ui", &["@app/core"]),
+            member("@app/core", &[]),
+            member("tools", &[]),
+        ]
+    }
+
+    fn filter(filters: &[&str]) -> Vec<usize> {
+        let root = env::current_dir().unwrap();
+        let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+        filter_members(&root, &members(), &filters).unwrap()
+    }
+
+    #[test]
+    fn filters_by_name_and_path() {
+        assert_eq!(filter(&["tools"]), [3]);
+        assert_eq!(filter(&["@app/*"]), [0, 1, 2]);
+        assert_eq!(filter(&["@app/core", "tools"]), [2, 3]);
+        assert_eq!(filter(&["{packages/ui}"]), [1]);
+        assert_eq!(filter(&["./packages"]), [0, 1, 2, 3]);
+        assert!(filter(&["missing"]).is_empty());
+    }
+
+    #[test]
+    fn adds_dependencies_and_dependents() {
+        assert_eq!(filter(&["@app/web..."]), [0, 1, 2]);
+        assert_eq!(filter(&["...@app/core"]), [0, 1, 2]);
+        assert_eq!(filter(&["...@app/ui"]), [0, 1]);
+        assert_eq!(filter(&["@app/ui..."]), [1, 2]);
+    }
+
+    #[test]
+    fn excludes_negated_filters() {
+        assert_eq!(filter(&["!tools"]), [0, 1, 2]);
+        assert_eq!(filter(&["@app/*", "!@app/web"]), [1, 2]);
+        assert_eq!(filter(&["!@app/web..."]), [3]);
+    }
+
+    #[test]
+    fn orders_dependencies_first() {
+        let graph = dependency_graph(&members());
+        assert_eq!(graph, [vec![1], vec![2], vec![], vec![]]);
+        assert_eq!(topological_order(&graph, &[0, 1, 2, 3]), [2, 3, 1, 0]);
+        assert_eq!(topological_order(&graph, &[0, 2]), [2, 0]);
+        assert_eq!(dependencies_of(&graph, 0), [1, 2]);
+        assert_eq!(dependencies_of(&graph, 3), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn keeps_cycles_in_declaration_order() {
+        let members = vec![member("a", &["b"]), member("b", &["a"]), member("c", &["a"])];
+        let graph = dependency_graph(&members);
+        assert_eq!(topological_order(&graph, &[0, 1, 2]), [0, 1, 2]);
+
+        let members = vec![member("a", &["b"]), member("b", &["a"]), member("c", &[])];
+        let graph = dependency_graph(&members);
+        assert_eq!(topological_order(&graph, &[0, 1, 2]), [2, 0, 1]);
+    }
+}